@@ -0,0 +1,15 @@
+CREATE TABLE public.refresh_tokens
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    family TEXT NOT NULL,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NULL,
+    replaced_by INT NULL,
+    CONSTRAINT refresh_tokens_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    CONSTRAINT refresh_tokens_replaced_by_fk FOREIGN KEY (replaced_by) REFERENCES refresh_tokens (id) ON DELETE SET NULL
+);
+CREATE UNIQUE INDEX refresh_tokens_token_hash_uindex ON public.refresh_tokens (token_hash);
+CREATE INDEX refresh_tokens_family_index ON public.refresh_tokens (family);
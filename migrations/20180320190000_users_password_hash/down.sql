@@ -0,0 +1,4 @@
+ALTER TABLE public.users ADD token TEXT NULL;
+UPDATE public.users SET token = password_hash;
+ALTER TABLE public.users ALTER COLUMN token SET NOT NULL;
+ALTER TABLE public.users DROP password_hash;
@@ -0,0 +1,4 @@
+ALTER TABLE public.users ADD password_hash TEXT NULL;
+UPDATE public.users SET password_hash = token;
+ALTER TABLE public.users ALTER COLUMN password_hash SET NOT NULL;
+ALTER TABLE public.users DROP token;
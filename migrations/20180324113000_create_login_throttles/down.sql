@@ -0,0 +1 @@
+DROP TABLE public.login_throttles;
\ No newline at end of file
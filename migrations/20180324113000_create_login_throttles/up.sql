@@ -0,0 +1,10 @@
+CREATE TABLE public.login_throttles
+(
+    id SERIAL PRIMARY KEY,
+    scope TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    failures INT NOT NULL,
+    last_failure_at TIMESTAMPTZ NOT NULL,
+    locked_until TIMESTAMPTZ NULL
+);
+CREATE UNIQUE INDEX login_throttles_scope_subject_uindex ON public.login_throttles (scope, subject);
@@ -0,0 +1 @@
+ALTER TABLE public.users DROP email_verified_at;
\ No newline at end of file
@@ -0,0 +1,3 @@
+ALTER TABLE public.users ADD email_verified_at TIMESTAMPTZ NULL;
+-- Accounts created before verification existed are trusted as they are.
+UPDATE public.users SET email_verified_at = now();
@@ -0,0 +1,2 @@
+ALTER TABLE public.users DROP tokens_valid_after;
+DROP TABLE public.password_resets;
@@ -0,0 +1,13 @@
+CREATE TABLE public.password_resets
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    token_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ NULL,
+    CONSTRAINT password_resets_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX password_resets_token_hash_uindex ON public.password_resets (token_hash);
+CREATE INDEX password_resets_user_index ON public.password_resets (user_id);
+ALTER TABLE public.users ADD tokens_valid_after TIMESTAMPTZ NULL;
@@ -0,0 +1,4 @@
+DROP TABLE public.recovery_codes;
+ALTER TABLE public.users DROP totp_secret;
+ALTER TABLE public.users DROP totp_enabled_at;
+ALTER TABLE public.users DROP totp_last_step;
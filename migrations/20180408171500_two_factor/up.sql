@@ -0,0 +1,12 @@
+ALTER TABLE public.users ADD totp_secret TEXT NULL;
+ALTER TABLE public.users ADD totp_enabled_at TIMESTAMPTZ NULL;
+ALTER TABLE public.users ADD totp_last_step BIGINT NULL;
+CREATE TABLE public.recovery_codes
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMPTZ NULL,
+    CONSTRAINT recovery_codes_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+CREATE INDEX recovery_codes_user_index ON public.recovery_codes (user_id);
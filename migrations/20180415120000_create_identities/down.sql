@@ -0,0 +1,2 @@
+DROP TABLE public.oauth_states;
+DROP TABLE public.identities;
@@ -0,0 +1,23 @@
+CREATE TABLE public.identities
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    CONSTRAINT identities_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX identities_provider_subject_uindex ON public.identities (provider, subject);
+CREATE INDEX identities_user_index ON public.identities (user_id);
+
+CREATE TABLE public.oauth_states
+(
+    id SERIAL PRIMARY KEY,
+    state TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+CREATE UNIQUE INDEX oauth_states_state_uindex ON public.oauth_states (state);
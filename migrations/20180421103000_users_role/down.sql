@@ -0,0 +1,2 @@
+ALTER TABLE public.users DROP CONSTRAINT users_role_check;
+ALTER TABLE public.users DROP role;
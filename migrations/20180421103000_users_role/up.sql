@@ -0,0 +1,2 @@
+ALTER TABLE public.users ADD role TEXT NOT NULL DEFAULT 'user';
+ALTER TABLE public.users ADD CONSTRAINT users_role_check CHECK (role IN ('user', 'moderator', 'admin'));
@@ -0,0 +1,2 @@
+ALTER TABLE public.users DROP suspended_at;
+ALTER TABLE public.users DROP suspended_reason;
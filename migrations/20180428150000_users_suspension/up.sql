@@ -0,0 +1,2 @@
+ALTER TABLE public.users ADD suspended_at TIMESTAMPTZ NULL;
+ALTER TABLE public.users ADD suspended_reason TEXT NULL;
@@ -0,0 +1,14 @@
+CREATE TABLE public.personal_tokens
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    expires_at TIMESTAMPTZ NULL,
+    last_used_at TIMESTAMPTZ NULL,
+    CONSTRAINT personal_tokens_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX personal_tokens_token_hash_uindex ON public.personal_tokens (token_hash);
+CREATE INDEX personal_tokens_user_index ON public.personal_tokens (user_id);
@@ -0,0 +1,15 @@
+CREATE TABLE public.sessions
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    family TEXT NOT NULL,
+    device_name TEXT NULL,
+    user_agent TEXT NULL,
+    ip TEXT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    last_seen_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NULL,
+    CONSTRAINT sessions_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX sessions_family_uindex ON public.sessions (family);
+CREATE INDEX sessions_user_index ON public.sessions (user_id);
@@ -0,0 +1,4 @@
+DROP INDEX public.users_username_uindex;
+CREATE UNIQUE INDEX users_username_uindex ON public.users (username);
+DROP INDEX public.users_email_uindex;
+CREATE UNIQUE INDEX users_email_uindex ON public.users (email);
@@ -0,0 +1,27 @@
+-- Refuse to migrate while accounts differ only in case, an admin has to
+-- merge or rename them first.
+DO $$
+DECLARE
+    collisions TEXT;
+BEGIN
+    SELECT string_agg(normalized, ', ') INTO collisions FROM (
+        SELECT lower(trim(email)) AS normalized FROM users GROUP BY 1 HAVING count(*) > 1
+    ) duplicates;
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION 'emails differing only in case or whitespace: %', collisions;
+    END IF;
+
+    SELECT string_agg(normalized, ', ') INTO collisions FROM (
+        SELECT lower(trim(username)) AS normalized FROM users GROUP BY 1 HAVING count(*) > 1
+    ) duplicates;
+    IF collisions IS NOT NULL THEN
+        RAISE EXCEPTION 'usernames differing only in case or whitespace: %', collisions;
+    END IF;
+END $$;
+
+UPDATE public.users SET email = lower(trim(email)), username = trim(username);
+
+DROP INDEX public.users_email_uindex;
+CREATE UNIQUE INDEX users_email_uindex ON public.users (lower(email));
+DROP INDEX public.users_username_uindex;
+CREATE UNIQUE INDEX users_username_uindex ON public.users (lower(username));
@@ -0,0 +1,10 @@
+CREATE TABLE public.username_history
+(
+    id SERIAL PRIMARY KEY,
+    user_id INT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL,
+    CONSTRAINT username_history_users_id_fk FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+CREATE INDEX username_history_username_index ON public.username_history (lower(username));
+CREATE INDEX username_history_user_index ON public.username_history (user_id);
@@ -0,0 +1,4 @@
+DROP TRIGGER articles_search_vector_update ON public.articles;
+DROP FUNCTION articles_search_vector();
+ALTER TABLE public.articles DROP COLUMN search_vector;
+ALTER TABLE public.articles DROP COLUMN language;
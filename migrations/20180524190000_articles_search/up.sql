@@ -0,0 +1,23 @@
+-- Text search configuration (`pg_ts_config`) the article is written in,
+-- decides how its words are stemmed.
+ALTER TABLE public.articles ADD COLUMN language TEXT NOT NULL DEFAULT 'english';
+ALTER TABLE public.articles ADD COLUMN search_vector TSVECTOR;
+
+CREATE FUNCTION articles_search_vector() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.search_vector :=
+        setweight(to_tsvector(NEW.language::regconfig, NEW.title), 'A') ||
+        setweight(to_tsvector(NEW.language::regconfig, coalesce(array_to_string(NEW.tag_list, ' '), '')), 'B') ||
+        setweight(to_tsvector(NEW.language::regconfig, NEW.description), 'B') ||
+        setweight(to_tsvector(NEW.language::regconfig, NEW.body), 'C');
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER articles_search_vector_update
+    BEFORE INSERT OR UPDATE OF title, description, body, tag_list, language ON public.articles
+    FOR EACH ROW EXECUTE PROCEDURE articles_search_vector();
+
+UPDATE public.articles SET language = language;
+ALTER TABLE public.articles ALTER COLUMN search_vector SET NOT NULL;
+CREATE INDEX articles_search_vector_index ON public.articles USING GIN (search_vector);
@@ -0,0 +1 @@
+DROP INDEX public.articles_created_at_id_index;
@@ -0,0 +1 @@
+CREATE INDEX articles_created_at_id_index ON public.articles (created_at, id);
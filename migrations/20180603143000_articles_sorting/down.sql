@@ -0,0 +1,9 @@
+DROP TRIGGER comments_count_update ON public.comments;
+DROP FUNCTION articles_comments_count();
+DROP TRIGGER favorites_count_update ON public.favorites;
+DROP FUNCTION articles_favorites_count();
+DROP TRIGGER articles_hot_score_update ON public.articles;
+DROP FUNCTION articles_hot_score();
+ALTER TABLE public.articles DROP COLUMN hot_score;
+ALTER TABLE public.articles DROP COLUMN comments_count;
+ALTER TABLE public.articles DROP COLUMN favorites_count;
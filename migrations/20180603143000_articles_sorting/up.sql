@@ -0,0 +1,59 @@
+-- Every sort order of the article list walks a (key, id) index, so the
+-- keys it sorts by are stored on the article and kept current by triggers.
+ALTER TABLE public.articles ADD COLUMN favorites_count INT NOT NULL DEFAULT 0;
+ALTER TABLE public.articles ADD COLUMN comments_count INT NOT NULL DEFAULT 0;
+ALTER TABLE public.articles ADD COLUMN hot_score DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+-- Trending order: engagement counts logarithmically, and every 12.5 hours
+-- of age weigh as much as e times the engagement. Unlike dividing by the
+-- age, this does not change as time passes, so it can be stored and indexed.
+CREATE FUNCTION articles_hot_score() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.hot_score := ln(1 + NEW.favorites_count + 2 * NEW.comments_count)
+        + extract(EPOCH FROM NEW.created_at) / 45000;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER articles_hot_score_update
+    BEFORE INSERT OR UPDATE OF favorites_count, comments_count, created_at ON public.articles
+    FOR EACH ROW EXECUTE PROCEDURE articles_hot_score();
+
+CREATE FUNCTION articles_favorites_count() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE public.articles SET favorites_count = favorites_count + 1 WHERE id = NEW.article_id;
+    ELSE
+        UPDATE public.articles SET favorites_count = favorites_count - 1 WHERE id = OLD.article_id;
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER favorites_count_update
+    AFTER INSERT OR DELETE ON public.favorites
+    FOR EACH ROW EXECUTE PROCEDURE articles_favorites_count();
+
+CREATE FUNCTION articles_comments_count() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE public.articles SET comments_count = comments_count + 1 WHERE id = NEW.article_id;
+    ELSE
+        UPDATE public.articles SET comments_count = comments_count - 1 WHERE id = OLD.article_id;
+    END IF;
+    RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER comments_count_update
+    AFTER INSERT OR DELETE ON public.comments
+    FOR EACH ROW EXECUTE PROCEDURE articles_comments_count();
+
+UPDATE public.articles SET
+    favorites_count = (SELECT count(*) FROM public.favorites WHERE article_id = articles.id),
+    comments_count = (SELECT count(*) FROM public.comments WHERE article_id = articles.id);
+
+CREATE INDEX articles_updated_at_id_index ON public.articles (updated_at, id);
+CREATE INDEX articles_favorites_count_id_index ON public.articles (favorites_count, id);
+CREATE INDEX articles_comments_count_id_index ON public.articles (comments_count, id);
+CREATE INDEX articles_hot_score_id_index ON public.articles (hot_score, id);
@@ -0,0 +1 @@
+DROP INDEX public.articles_tag_list_index;
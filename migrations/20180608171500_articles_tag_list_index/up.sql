@@ -0,0 +1 @@
+CREATE INDEX articles_tag_list_index ON public.articles USING GIN (tag_list);
@@ -0,0 +1,16 @@
+DROP TRIGGER articles_hot_score_update ON public.articles;
+CREATE OR REPLACE FUNCTION articles_hot_score() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.hot_score := ln(1 + NEW.favorites_count + 2 * NEW.comments_count)
+        + extract(EPOCH FROM NEW.created_at) / 45000;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+CREATE TRIGGER articles_hot_score_update
+    BEFORE INSERT OR UPDATE OF favorites_count, comments_count, created_at ON public.articles
+    FOR EACH ROW EXECUTE PROCEDURE articles_hot_score();
+
+DROP INDEX public.articles_unpublished_index;
+DROP INDEX public.articles_published_at_id_index;
+ALTER TABLE public.articles DROP COLUMN published_at;
+ALTER TABLE public.articles DROP COLUMN status;
@@ -0,0 +1,29 @@
+ALTER TABLE public.articles ADD status TEXT NOT NULL DEFAULT 'published';
+ALTER TABLE public.articles ADD CONSTRAINT articles_status_check
+    CHECK (status IN ('draft', 'scheduled', 'published', 'unlisted'));
+
+-- When the article went or goes public. Scheduled articles show up in
+-- lists once it has passed, drafts have none.
+ALTER TABLE public.articles ADD published_at TIMESTAMPTZ;
+UPDATE public.articles SET published_at = created_at;
+ALTER TABLE public.articles ADD CONSTRAINT articles_published_at_check
+    CHECK (status = 'draft' OR published_at IS NOT NULL);
+
+CREATE INDEX articles_published_at_id_index ON public.articles (published_at, id);
+CREATE INDEX articles_unpublished_index ON public.articles (author_id, updated_at)
+    WHERE status IN ('draft', 'scheduled');
+
+-- Trending counts an article's age from when it went public.
+CREATE OR REPLACE FUNCTION articles_hot_score() RETURNS TRIGGER AS $$
+BEGIN
+    NEW.hot_score := ln(1 + NEW.favorites_count + 2 * NEW.comments_count)
+        + extract(EPOCH FROM coalesce(NEW.published_at, NEW.created_at)) / 45000;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER articles_hot_score_update ON public.articles;
+CREATE TRIGGER articles_hot_score_update
+    BEFORE INSERT OR UPDATE OF favorites_count, comments_count, created_at, published_at
+    ON public.articles
+    FOR EACH ROW EXECUTE PROCEDURE articles_hot_score();
@@ -0,0 +1 @@
+DROP FUNCTION html_escape(TEXT);
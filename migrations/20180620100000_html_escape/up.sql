@@ -0,0 +1,6 @@
+-- Escapes text for HTML, so search highlights can be built from article
+-- content without letting that content inject markup.
+CREATE FUNCTION html_escape(TEXT) RETURNS TEXT AS $$
+    SELECT replace(replace(replace(replace(replace($1,
+        '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '"', '&quot;'), '''', '&#39;')
+$$ LANGUAGE sql IMMUTABLE STRICT;
@@ -0,0 +1,3 @@
+DROP INDEX public.username_history_skeleton_index;
+DROP INDEX public.users_username_skeleton_index;
+DROP FUNCTION username_skeleton(TEXT);
@@ -0,0 +1,11 @@
+-- The skeleton usernames are compared by when looking for confusable names.
+-- Has to match `HOMOGLYPHS` in src/users/utils.rs.
+CREATE FUNCTION username_skeleton(TEXT) RETURNS TEXT AS $$
+    SELECT translate(lower($1),
+        'авекмнорстухіјѕԁһӏαβεικνορτυχ015-.',
+        'abekmhopctyxijsdhlabeikvoptuxols__')
+$$ LANGUAGE sql IMMUTABLE STRICT;
+
+CREATE INDEX users_username_skeleton_index ON public.users (username_skeleton(username));
+CREATE INDEX username_history_skeleton_index
+    ON public.username_history (username_skeleton(username));
@@ -0,0 +1,292 @@
+use chrono::{DateTime, Utc};
+use db::schema::users;
+use db::DbConnection;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::{delete as diesel_delete, update as diesel_update};
+use mailer::Mailer;
+use pagination::Page;
+use policy::{self, Permission, Role};
+use rocket::State;
+use rocket_contrib::Json;
+use types::{ApiError, ApiResult, ValidationError};
+use users::models::User;
+use users::refresh::RefreshToken;
+use users::reset::PasswordReset;
+use users::throttle::{self, LoginThrottle};
+use users::tokens::PersonalToken;
+use users::CurrentUser;
+use utils::random_token;
+
+fn require_admin(current_user: CurrentUser) -> Result<User, ApiError> {
+    let user = current_user?;
+    policy::authorize(&user, Permission::ManageUsers, None)?;
+    Ok(user)
+}
+
+// Admins cannot lock themselves out by suspending, demoting or deleting
+// their own account.
+fn not_self(admin: &User, user: &User) -> Result<(), ApiError> {
+    if admin.id == user.id {
+        let error = ValidationError::from("username", "Admins cannot do this to their own account");
+        Err(error.into())
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminUserView {
+    id: i32,
+    username: String,
+    email: String,
+    bio: Option<String>,
+    image: Option<String>,
+    role: Role,
+    email_verified_at: Option<DateTime<Utc>>,
+    two_factor_enabled: bool,
+    suspended_at: Option<DateTime<Utc>>,
+    suspended_reason: Option<String>,
+}
+
+impl From<User> for AdminUserView {
+    fn from(user: User) -> Self {
+        AdminUserView {
+            two_factor_enabled: user.has_two_factor(),
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            bio: user.bio,
+            image: user.image,
+            role: user.role,
+            email_verified_at: user.email_verified_at,
+            suspended_at: user.suspended_at,
+            suspended_reason: user.suspended_reason,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminUserResponse {
+    user: AdminUserView,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminUserList {
+    users: Vec<AdminUserView>,
+    users_count: i64,
+}
+
+#[derive(FromForm, Default, Debug)]
+pub struct UserFilter {
+    q: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+fn search<'a>(q: &Option<String>) -> users::BoxedQuery<'a, Pg> {
+    let mut query = users::table.into_boxed::<Pg>();
+    if let Some(ref q) = *q {
+        let escaped = q.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        let pattern = format!("%{}%", escaped);
+        query = query.filter(
+            users::username
+                .ilike(pattern.clone())
+                .or(users::email.ilike(pattern)),
+        );
+    }
+    query
+}
+
+#[get("/users?<filter>", format = "application/json")]
+pub fn list_users(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    filter: UserFilter,
+) -> ApiResult<AdminUserList> {
+    handle_list_users(connection, current_user, filter)
+}
+
+#[get("/users", format = "application/json")]
+pub fn list_users_without_params(
+    connection: DbConnection,
+    current_user: CurrentUser,
+) -> ApiResult<AdminUserList> {
+    handle_list_users(connection, current_user, UserFilter::default())
+}
+
+fn handle_list_users(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    filter: UserFilter,
+) -> ApiResult<AdminUserList> {
+    require_admin(current_user)?;
+    let page = Page::new(filter.limit, filter.offset, None)?;
+    let users_count = search(&filter.q).count().get_result::<i64>(&*connection)?;
+    let users = search(&filter.q)
+        .order(users::id.asc())
+        .offset(page.offset)
+        .limit(page.limit)
+        .get_results::<User>(&*connection)?;
+
+    Ok(Json(AdminUserList {
+        users: users.into_iter().map(AdminUserView::from).collect(),
+        users_count,
+    }))
+}
+
+#[get("/users/<username>", format = "application/json")]
+pub fn get_user(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    username: String,
+) -> ApiResult<AdminUserResponse> {
+    require_admin(current_user)?;
+    let user = User::load_by_name(&username, &connection)?;
+    Ok(Json(AdminUserResponse { user: user.into() }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Suspension {
+    reason: Option<String>,
+}
+
+#[post("/users/<username>/suspend", format = "application/json", data = "<suspension>")]
+pub fn suspend_user(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    username: String,
+    suspension: Json<Suspension>,
+) -> ApiResult<AdminUserResponse> {
+    let admin = require_admin(current_user)?;
+    let user = User::load_by_name(&username, &connection)?;
+    not_self(&admin, &user)?;
+
+    connection.transaction::<_, ApiError, _>(|| {
+        diesel_update(&user)
+            .set((
+                users::suspended_at.eq(Utc::now()),
+                users::suspended_reason.eq(&suspension.reason),
+            ))
+            .execute(&*connection)?;
+        RefreshToken::revoke_all(user.id, &connection)?;
+        Ok(())
+    })?;
+
+    let user = User::load_by_id(&user.id, &connection)?;
+    Ok(Json(AdminUserResponse { user: user.into() }))
+}
+
+#[post("/users/<username>/unsuspend", format = "application/json")]
+pub fn unsuspend_user(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    username: String,
+) -> ApiResult<AdminUserResponse> {
+    require_admin(current_user)?;
+    let user = User::load_by_name(&username, &connection)?;
+    diesel_update(&user)
+        .set((
+            users::suspended_at.eq(None::<DateTime<Utc>>),
+            users::suspended_reason.eq(None::<String>),
+        ))
+        .execute(&*connection)?;
+
+    let user = User::load_by_id(&user.id, &connection)?;
+    Ok(Json(AdminUserResponse { user: user.into() }))
+}
+
+/// Replaces the password with an unknown one, signs the user out everywhere,
+/// drops their personal access tokens and mails them a reset link.
+#[post("/users/<username>/password-reset", format = "application/json")]
+pub fn force_password_reset(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    mailer: State<Box<Mailer>>,
+    username: String,
+) -> ApiResult<()> {
+    require_admin(current_user)?;
+    let mut user = User::load_by_name(&username, &connection)?;
+
+    connection.transaction::<_, ApiError, _>(|| {
+        user.new_password(&random_token(32)?)?;
+        diesel_update(&user)
+            .set((
+                users::password_hash.eq(&user.password_hash),
+                users::tokens_valid_after.eq(Utc::now()),
+            ))
+            .execute(&*connection)?;
+        RefreshToken::revoke_all(user.id, &connection)?;
+        PersonalToken::revoke_all(user.id, &connection)?;
+        PasswordReset::request(&user.email, &connection, &**mailer)
+    })?;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleChange {
+    role: Role,
+}
+
+#[put("/users/<username>/role", format = "application/json", data = "<change>")]
+pub fn change_role(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    username: String,
+    change: Json<RoleChange>,
+) -> ApiResult<AdminUserResponse> {
+    let admin = require_admin(current_user)?;
+    let user = User::load_by_name(&username, &connection)?;
+    not_self(&admin, &user)?;
+
+    diesel_update(&user)
+        .set(users::role.eq(change.role))
+        .execute(&*connection)?;
+
+    let user = User::load_by_id(&user.id, &connection)?;
+    Ok(Json(AdminUserResponse { user: user.into() }))
+}
+
+/// Deletes the account for good. Articles, comments, favorites and follows
+/// go with it through the foreign keys.
+#[delete("/users/<username>", format = "application/json")]
+pub fn delete_user(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    username: String,
+) -> ApiResult<()> {
+    let admin = require_admin(current_user)?;
+    let user = User::load_by_name(&username, &connection)?;
+    not_self(&admin, &user)?;
+
+    diesel_delete(&user).execute(&*connection)?;
+    Ok(Json(()))
+}
+
+#[delete("/lockouts/<scope>/<subject>", format = "application/json")]
+pub fn clear_lockout(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    scope: String,
+    subject: String,
+) -> ApiResult<()> {
+    require_admin(current_user)?;
+    let scopes = [
+        throttle::ACCOUNT,
+        throttle::ACCOUNT_CLIENT_IP,
+        throttle::CLIENT_IP,
+        throttle::MAIL,
+        throttle::MAIL_CLIENT_IP,
+    ];
+    if !scopes.contains(&scope.as_str()) {
+        let error = ValidationError::from("scope", format!("Unknown lockout scope: {}", scope));
+        return Err(error.into());
+    }
+
+    LoginThrottle::clear(&scope, &subject, &connection)?;
+    Ok(Json(()))
+}
@@ -1,31 +1,39 @@
 use chrono::format::{Fixed, Item, Numeric, Pad};
-use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use config::CONFIG;
 use db::schema::{articles, favorites, followers, users};
 use db::DbConnection;
 use diesel::associations::HasTable;
 use diesel::dsl::sql;
-use diesel::dsl::{count, count_star, exists, Eq, Filter, Limit, Offset};
+use diesel::dsl::{count, count_star, exists, not, Eq, Filter, Limit, Offset};
 use diesel::expression::{AsExpression, BoxableExpression, Expression, SelectableExpression};
 use diesel::pg::expression::dsl::any;
 use diesel::pg::types::sql_types::Array;
 use diesel::pg::Pg;
 use diesel::prelude::*;
 use diesel::query_dsl;
+use diesel::deserialize::{self, FromSql};
 use diesel::result::{DatabaseErrorKind, Error};
-use diesel::sql_types::{BigInt, Bool, Integer, Nullable, Text, Timestamptz};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{BigInt, Bool, Float, Integer, Nullable, Text, Timestamptz};
 use diesel::PgArrayExpressionMethods;
 use diesel::{debug_query, delete as diesel_delete, select};
 use diesel::{insert_into, sql_query, update as diesel_update};
+use pagination::{invalid_cursor, parse_time_key, time_key, Cursor, Direction, Page};
+use policy::{self, scope, Permission};
 use profile::Profile;
 use regex::Regex;
+use rocket::request::{FormItems, FromForm};
 use rocket_contrib::Json;
 use serde::ser::{Serialize, SerializeStruct, Serializer};
 use slug::slugify;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use types::*;
 use users::models::User;
-use users::CurrentUser;
+use users::{optional_user, CurrentUser, ScopedUser};
 use utils;
 
 allow_tables_to_appear_in_same_query!(users, articles);
@@ -33,8 +41,52 @@ allow_tables_to_appear_in_same_query!(users, favorites);
 allow_tables_to_appear_in_same_query!(users, followers);
 allow_tables_to_appear_in_same_query!(articles, favorites);
 
-#[derive(Identifiable, Queryable, Associations, PartialEq, Debug, Deserialize, Serialize,
-         AsChangeset)]
+/// Who gets to see an article. Scheduled articles turn public once their
+/// `published_at` has passed, unlisted ones are public but left out of
+/// lists and search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum ArticleStatus {
+    Draft,
+    Scheduled,
+    Published,
+    Unlisted,
+}
+
+impl ArticleStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ArticleStatus::Draft => "draft",
+            ArticleStatus::Scheduled => "scheduled",
+            ArticleStatus::Published => "published",
+            ArticleStatus::Unlisted => "unlisted",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for ArticleStatus {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for ArticleStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"draft" => Ok(ArticleStatus::Draft),
+            b"scheduled" => Ok(ArticleStatus::Scheduled),
+            b"published" => Ok(ArticleStatus::Published),
+            b"unlisted" => Ok(ArticleStatus::Unlisted),
+            _ => Err("Unrecognized article status".into()),
+        }
+    }
+}
+
+// Deliberately not `AsChangeset`: the counters and `hot_score` are kept by
+// triggers, writing back a loaded article would undo concurrent updates.
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug, Deserialize, Serialize)]
 #[belongs_to(User, foreign_key = "author_id")]
 #[table_name = "articles"]
 #[serde(rename_all = "camelCase")]
@@ -51,6 +103,16 @@ pub struct Article {
     pub tag_list: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub language: String,
+    #[serde(skip)]
+    pub favorites_count: i32,
+    #[serde(skip)]
+    pub comments_count: i32,
+    /// Position in the trending order, see the `articles_hot_score` trigger.
+    #[serde(skip)]
+    pub hot_score: f64,
+    pub status: ArticleStatus,
+    pub published_at: Option<DateTime<Utc>>,
 }
 
 impl Article {
@@ -62,6 +124,42 @@ impl Article {
             .map_err(|e| e.into())
     }
 
+    /// Like `load_by_slug`, but articles `user` may not read yet are not
+    /// found either.
+    pub fn load_readable(
+        slug_: &str,
+        user: Option<&User>,
+        connection: &PgConnection,
+    ) -> Result<Article, ApiError> {
+        let article = Article::load_by_slug(slug_, connection)?;
+        if article.is_readable_by(user) {
+            Ok(article)
+        } else {
+            Err(Error::NotFound.into())
+        }
+    }
+
+    /// `status`, with scheduled articles whose time has come counting as
+    /// published.
+    pub fn current_status(&self) -> ArticleStatus {
+        match (self.status, self.published_at) {
+            (ArticleStatus::Scheduled, Some(published_at)) if published_at <= Utc::now() => {
+                ArticleStatus::Published
+            }
+            (status, _) => status,
+        }
+    }
+
+    /// Drafts and articles waiting for their time are only for their author.
+    pub fn is_readable_by(&self, user: Option<&User>) -> bool {
+        match self.current_status() {
+            ArticleStatus::Draft | ArticleStatus::Scheduled => {
+                user.map_or(false, |user| user.id == self.author_id)
+            }
+            ArticleStatus::Published | ArticleStatus::Unlisted => true,
+        }
+    }
+
     pub fn by_slug<'r>(article_slug: &'r str) -> BySlug<'r> {
         use db::schema::articles::dsl::*;
         let condition = slug.eq(article_slug);
@@ -118,6 +216,13 @@ pub struct RichArticle<'a> {
     #[serde(serialize_with = "utils::serialize_date")]
     #[column_name = "updated_at"]
     updated_at: DateTime<Utc>,
+    #[sql_type = "Text"]
+    language: String,
+    #[sql_type = "Text"]
+    status: ArticleStatus,
+    #[sql_type = "Nullable<Timestamptz>"]
+    #[serde(serialize_with = "utils::serialize_optional_date")]
+    published_at: Option<DateTime<Utc>>,
     #[sql_type = "BigInt"]
     favorites_count: i64,
 
@@ -125,6 +230,18 @@ pub struct RichArticle<'a> {
     favorited: bool,
     #[diesel(embed)]
     author: Profile<'a>,
+
+    // Only set on search results. The highlights are HTML: the article text
+    // in them is escaped, so `<mark>` is the only markup they can contain.
+    #[sql_type = "Nullable<Float>"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rank: Option<f32>,
+    #[sql_type = "Nullable<Text>"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title_highlight: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    snippet: Option<String>,
 }
 
 type WithSlug<'a> = Eq<articles::slug, &'a str>;
@@ -141,6 +258,7 @@ impl<'a> RichArticle<'a> {
             Some(c) => c,
             None => 0,
         };
+        let status = article.current_status();
         RichArticle {
             id: article.id,
             slug: article.slug,
@@ -150,9 +268,15 @@ impl<'a> RichArticle<'a> {
             tag_list: Some(article.tag_list),
             created_at: article.created_at,
             updated_at: article.updated_at,
+            language: article.language,
+            status,
+            published_at: article.published_at,
             favorites_count: favorites_count,
             favorited: favorited,
             author: author,
+            rank: None,
+            title_highlight: None,
+            snippet: None,
         }
     }
 }
@@ -168,6 +292,9 @@ pub struct NewArticle {
     tag_list: Vec<String>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
+    language: String,
+    status: ArticleStatus,
+    published_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -179,6 +306,15 @@ pub struct ArticleDetails {
     #[serde(default)]
     #[serde(rename = "tagList")]
     tag_list: Vec<String>,
+    #[serde(default)]
+    language: Option<String>,
+    /// Defaults to published.
+    #[serde(default)]
+    status: Option<ArticleStatus>,
+    /// Required for scheduled articles, ignored otherwise.
+    #[serde(default)]
+    #[serde(rename = "publishedAt")]
+    published_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -187,8 +323,8 @@ pub struct CreateArticle {
 }
 
 impl Validate for CreateArticle {
-    type Error = ValidationError;
-    fn validate(self, connection: &PgConnection) -> Result<Self, ValidationError> {
+    type Error = ApiError;
+    fn validate(self, connection: &PgConnection) -> Result<Self, ApiError> {
         match CreateOrUpdate::Create(&self).validate(connection) {
             Ok(_) => Ok(self),
             Err(e) => Err(e),
@@ -197,8 +333,8 @@ impl Validate for CreateArticle {
 }
 
 impl Validate for UpdateArticle {
-    type Error = ValidationError;
-    fn validate(self, connection: &PgConnection) -> Result<Self, ValidationError> {
+    type Error = ApiError;
+    fn validate(self, connection: &PgConnection) -> Result<Self, ApiError> {
         match CreateOrUpdate::Update(&self).validate(connection) {
             Ok(_) => Ok(self),
             Err(e) => Err(e),
@@ -222,9 +358,36 @@ fn add_error_if_empty(
     }
 }
 
+#[derive(QueryableByName)]
+struct Exists {
+    #[sql_type = "Bool"]
+    exists: bool,
+}
+
+/// Whether `name` is a text search configuration Postgres knows about.
+fn is_search_language(name: &str, connection: &PgConnection) -> Result<bool, Error> {
+    sql_query("SELECT EXISTS(SELECT 1 FROM pg_ts_config WHERE cfgname = $1) AS exists")
+        .bind::<Text, _>(name)
+        .get_result::<Exists>(connection)
+        .map(|row| row.exists)
+}
+
+fn add_error_if_unknown_language(
+    language: &Option<String>,
+    error: &mut ValidationError,
+    connection: &PgConnection,
+) -> Result<(), ApiError> {
+    if let Some(ref language) = *language {
+        if !is_search_language(language, connection)? {
+            error.add_error("language", format!("unknown language: {}", language));
+        }
+    }
+    Ok(())
+}
+
 impl<'r> Validate for CreateOrUpdate<'r> {
-    type Error = ValidationError;
-    fn validate(self, _connection: &PgConnection) -> Result<Self, ValidationError> {
+    type Error = ApiError;
+    fn validate(self, connection: &PgConnection) -> Result<Self, ApiError> {
         let mut error = ValidationError::default();
         match self {
             CreateOrUpdate::Create(&CreateArticle { ref article }) => {
@@ -236,6 +399,7 @@ impl<'r> Validate for CreateOrUpdate<'r> {
                     "description",
                     "empty description",
                 );
+                add_error_if_unknown_language(&article.language, &mut error, connection)?;
             }
 
             CreateOrUpdate::Update(&UpdateArticle { ref article }) => {
@@ -248,13 +412,14 @@ impl<'r> Validate for CreateOrUpdate<'r> {
                 if let Some(ref description) = article.description {
                     add_error_if_empty(description, &mut error, "description", "empty description");
                 }
+                add_error_if_unknown_language(&article.language, &mut error, connection)?;
             }
         }
 
         if error.empty() {
             Ok(self)
         } else {
-            Err(error)
+            Err(error.into())
         }
     }
 }
@@ -262,13 +427,17 @@ impl<'r> Validate for CreateOrUpdate<'r> {
 #[post("/", format = "application/json", data = "<create>")]
 pub fn create(
     connection: DbConnection,
-    user: CurrentUser,
+    user: ScopedUser<scope::ArticlesWrite>,
     create: Json<CreateArticle>,
 ) -> ApiResult<RichArticleResponse<'static>> {
     use db::schema::articles::dsl::*;
     let created = Utc::now();
     let create = create.validate(&*connection)?.into_inner();
-    let user = user?;
+    let user = user?.user;
+    policy::authorize(&user, Permission::CreateArticle, None)?;
+    user.require_verified()?;
+    let status = create.article.status.unwrap_or(ArticleStatus::Published);
+    let published_at = publication_time(status, create.article.published_at, None)?;
     let new_article = NewArticle {
         author_id: user.id,
         slug: created.timestamp().to_string() + "-" + &slugify(&create.article.title),
@@ -278,6 +447,12 @@ pub fn create(
         created_at: created,
         updated_at: created,
         tag_list: create.article.tag_list,
+        language: create
+            .article
+            .language
+            .unwrap_or_else(|| CONFIG.search_language.clone()),
+        status,
+        published_at,
     };
     let article = insert_into(articles)
         .values(&new_article)
@@ -289,12 +464,40 @@ pub fn create(
     }))
 }
 
+/// When an article in `status` goes public: never for drafts, at the
+/// `requested` time for scheduled ones, and for the rest when it first did
+/// or else now.
+fn publication_time(
+    status: ArticleStatus,
+    requested: Option<DateTime<Utc>>,
+    previous: Option<DateTime<Utc>>,
+) -> Result<Option<DateTime<Utc>>, ValidationError> {
+    let now = Utc::now();
+    match status {
+        ArticleStatus::Draft => Ok(None),
+        ArticleStatus::Scheduled => match requested {
+            Some(requested) if requested > now => Ok(Some(requested)),
+            _ => Err(ValidationError::from(
+                "publishedAt",
+                "scheduled articles need a publishedAt in the future",
+            )),
+        },
+        ArticleStatus::Published | ArticleStatus::Unlisted => {
+            Ok(Some(previous.filter(|previous| *previous <= now).unwrap_or(now)))
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, AsChangeset)]
 #[table_name = "articles"]
 pub struct UpdateDetails {
     title: Option<String>,
     description: Option<String>,
     body: Option<String>,
+    language: Option<String>,
+    status: Option<ArticleStatus>,
+    #[serde(rename = "publishedAt")]
+    published_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -305,17 +508,15 @@ pub struct UpdateArticle {
 #[put("/<slug>", format = "application/json", data = "<update>")]
 pub fn update(
     slug: String,
-    current_user: CurrentUser,
+    current_user: ScopedUser<scope::ArticlesWrite>,
     update: Json<UpdateArticle>,
     connection: DbConnection,
 ) -> ApiResult<RichArticleResponse<'static>> {
     use db::schema::favorites::dsl::*;
 
-    let current_user = current_user?;
+    let current_user = current_user?.user;
     let mut article: Article = Article::by_slug(&slug).first(&*connection)?;
-    if article.author_id != current_user.id {
-        return Err(ApiError::Unauthorized);
-    }
+    policy::authorize(&current_user, Permission::UpdateArticle, Some(article.author_id))?;
 
     let update = update.validate(&*connection)?.into_inner();
     if let Some(title) = update.article.title {
@@ -331,9 +532,35 @@ pub fn update(
         article.description = description;
     }
 
+    if let Some(language) = update.article.language {
+        article.language = language;
+    }
+
+    if update.article.status.is_some() || update.article.published_at.is_some() {
+        let status = update.article.status.unwrap_or(article.status);
+        let requested = update.article.published_at.or(article.published_at);
+        let previous = match article.current_status() {
+            ArticleStatus::Published | ArticleStatus::Unlisted => article.published_at,
+            _ => None,
+        };
+        article.published_at = publication_time(status, requested, previous)?;
+        article.status = status;
+    }
+
     article.updated_at = Utc::now();
 
-    diesel_update(&article).set(&article).execute(&*connection)?;
+    diesel_update(&article)
+        .set((
+            articles::slug.eq(&article.slug),
+            articles::title.eq(&article.title),
+            articles::description.eq(&article.description),
+            articles::body.eq(&article.body),
+            articles::language.eq(&article.language),
+            articles::status.eq(article.status),
+            articles::published_at.eq(article.published_at),
+            articles::updated_at.eq(article.updated_at),
+        ))
+        .execute(&*connection)?;
     let favorited_count = article.get_favorites_count(&*connection)?;
 
     let favorited = article.is_favorited_by(&current_user, &*connection)?;
@@ -351,7 +578,7 @@ pub fn update(
 pub fn get(
     slug_: String,
     connection: DbConnection,
-    current_user: CurrentUser,
+    current_user: ScopedUser<scope::Read>,
 ) -> ApiResult<RichArticleResponse<'static>> {
     let data = articles::table
         .inner_join(users::table.on(articles::author_id.eq(users::id)))
@@ -361,27 +588,26 @@ pub fn get(
     let article = data.0;
     let author = data.1;
 
+    let current_user = optional_user(current_user)?;
+    if !article.is_readable_by(current_user.as_ref()) {
+        return Err(Error::NotFound.into());
+    }
+
     let mut favorited = false;
     let mut followed = false;
 
     let fav_count = article.get_favorites_count(&*connection)?;
 
-    match current_user {
-        Ok(user) => {
-            favorited = article.is_favorited_by(&user, &*connection)?;
-
-            followed = select(exists(
-                followers::table.filter(
-                    followers::follower_id
-                        .eq(&user.id)
-                        .and(followers::user_id.eq(&author.id)),
-                ),
-            )).get_result::<bool>(&*connection)?;
-        }
-        Err(e) => match e {
-            ApiError::Unauthorized => {}
-            e @ _ => return Err(e),
-        },
+    if let Some(user) = current_user {
+        favorited = article.is_favorited_by(&user, &*connection)?;
+
+        followed = select(exists(
+            followers::table.filter(
+                followers::follower_id
+                    .eq(&user.id)
+                    .and(followers::user_id.eq(&author.id)),
+            ),
+        )).get_result::<bool>(&*connection)?;
     }
 
     let rich_article = RichArticle::from(
@@ -399,16 +625,12 @@ pub fn get(
 pub fn favorite(
     slug: String,
     connection: DbConnection,
-    current_user: CurrentUser,
+    current_user: ScopedUser<scope::ArticlesWrite>,
 ) -> ApiResult<RichArticleResponse<'static>> {
-    use db::schema::articles::dsl as articles_dsl;
     use db::schema::favorites::dsl::*;
 
-    let current_user = current_user?;
-    let fav_article_id = articles_dsl::articles
-        .select(articles_dsl::id)
-        .filter(articles_dsl::slug.eq(&slug))
-        .first::<i32>(&*connection)?;
+    let current_user = current_user?.user;
+    let fav_article_id = Article::load_readable(&slug, Some(&current_user), &connection)?.id;
 
     insert_into(favorites)
         .values((&user_id.eq(current_user.id), &article_id.eq(fav_article_id)))
@@ -432,38 +654,183 @@ pub fn favorite(
     Ok(Json(RichArticleResponse { article: article }))
 }
 
+#[delete("/<slug>/favorite", format = "application/json")]
+pub fn unfavorite(
+    slug: String,
+    connection: DbConnection,
+    current_user: ScopedUser<scope::ArticlesWrite>,
+) -> ApiResult<RichArticleResponse<'static>> {
+    use db::schema::favorites::dsl::*;
+
+    let current_user = current_user?.user;
+    let article = Article::load_readable(&slug, Some(&current_user), &connection)?;
+
+    diesel_delete(
+        favorites
+            .filter(user_id.eq(current_user.id))
+            .filter(article_id.eq(article.id)),
+    ).execute(&*connection)?;
+
+    let fav_count = article.get_favorites_count(&connection)?;
+    let author = User::load_by_id(&article.author_id, &connection)?;
+    let following = author.is_followed_by(&current_user, &connection)?;
+
+    let article = RichArticle::from(article, author.profile(following), Some(fav_count), false);
+
+    Ok(Json(RichArticleResponse { article: article }))
+}
+
 #[delete("/<slug_>", format = "application/json")]
-fn delete(connection: DbConnection, current_user: CurrentUser, slug_: String) -> ApiResult<()> {
-    let current_user = current_user?;
+fn delete(
+    connection: DbConnection,
+    current_user: ScopedUser<scope::ArticlesWrite>,
+    slug_: String,
+) -> ApiResult<()> {
+    let current_user = current_user?.user;
     let article = Article::load_by_slug(&slug_, &*connection)?;
-    if article.author_id != current_user.id {
-        return Err(ApiError::Forbidden);
-    }
+    policy::authorize(&current_user, Permission::DeleteArticle, Some(article.author_id))?;
 
     diesel_delete(&article).execute(&*connection)?;
     Ok(Json(()))
 }
 
-#[derive(FromForm, Default, Debug)]
+/// Query of the article list. `tag`, `author` and `favorited` may repeat,
+/// and `-tag` and `-author` exclude articles.
+#[derive(Default, Debug)]
 struct ListFilter {
-    tag: Option<String>,
-    author: Option<String>,
-    favorited: Option<String>,
+    tag: Vec<String>,
+    /// Whether articles need every `tag` rather than any, `tag_match=all`.
+    all_tags: bool,
+    excluded_tags: Vec<String>,
+    author: Vec<String>,
+    excluded_authors: Vec<String>,
+    favorited: Vec<String>,
+    /// Only articles by authors this user follows.
+    followed_by: Option<String>,
+    // Inclusive lower and exclusive upper bounds, RFC 3339 or `YYYY-MM-DD`.
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    updated_after: Option<DateTime<Utc>>,
+    updated_before: Option<DateTime<Utc>>,
     limit: Option<i64>,
     offset: Option<i64>,
+    /// `nextCursor` or `prevCursor` of an earlier page, instead of `offset`.
+    cursor: Option<String>,
+    /// One of `newest` (the default), `oldest`, `favorited`, `commented`,
+    /// `updated` or `trending`.
+    sort: Option<String>,
+    /// Parameters that could not be parsed, reported before anything is
+    /// queried.
+    errors: ValidationError,
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| DateTime::from_utc(date.and_hms(0, 0, 0), Utc))
+        })
+}
+
+// Written out rather than derived: the derive neither collects repeated
+// keys nor reports malformed values, it just drops them.
+impl<'f> FromForm<'f> for ListFilter {
+    type Error = ();
+
+    fn from_form(items: &mut FormItems<'f>, _strict: bool) -> Result<ListFilter, ()> {
+        let mut filter = ListFilter::default();
+        for (key, value) in items {
+            let key = key.as_str();
+            let value = match value.url_decode() {
+                Ok(value) => value,
+                Err(_) => {
+                    filter.errors.add_error(key, "invalid encoding");
+                    continue;
+                }
+            };
+
+            match key {
+                "tag" => filter.tag.push(value),
+                "-tag" => filter.excluded_tags.push(value),
+                "tag_match" => match value.as_str() {
+                    "any" => filter.all_tags = false,
+                    "all" => filter.all_tags = true,
+                    _ => filter.errors.add_error(key, "must be any or all"),
+                },
+                "author" => filter.author.push(value),
+                "-author" => filter.excluded_authors.push(value),
+                "favorited" => filter.favorited.push(value),
+                "followed_by" => filter.followed_by = Some(value),
+                "created_after" | "created_before" | "updated_after" | "updated_before" => {
+                    let time = match parse_time(&value) {
+                        Some(time) => time,
+                        None => {
+                            filter.errors.add_error(key, format!("invalid date: {}", value));
+                            continue;
+                        }
+                    };
+                    match key {
+                        "created_after" => filter.created_after = Some(time),
+                        "created_before" => filter.created_before = Some(time),
+                        "updated_after" => filter.updated_after = Some(time),
+                        _ => filter.updated_before = Some(time),
+                    }
+                }
+                "limit" | "offset" => {
+                    let number = match value.parse::<i64>() {
+                        Ok(number) => number,
+                        Err(_) => {
+                            filter.errors.add_error(key, format!("not a number: {}", value));
+                            continue;
+                        }
+                    };
+                    if key == "limit" {
+                        filter.limit = Some(number);
+                    } else {
+                        filter.offset = Some(number);
+                    }
+                }
+                "cursor" => filter.cursor = Some(value),
+                "sort" => filter.sort = Some(value),
+                _ => {}
+            }
+        }
+        Ok(filter)
+    }
 }
 
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct ListResponse<'a> {
     articles: Vec<RichArticle<'a>>,
-    articles_count: usize,
+    /// Matching articles across all pages.
+    articles_count: i64,
+    /// Set once there are too many matches to count, `articles_count` is an
+    /// estimate then.
+    articles_count_estimated: bool,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
+}
+
+impl<'a> ListResponse<'a> {
+    fn empty() -> ListResponse<'a> {
+        ListResponse {
+            articles: Vec::new(),
+            articles_count: 0,
+            articles_count_estimated: false,
+            next_cursor: None,
+            prev_cursor: None,
+        }
+    }
 }
 
 #[get("/?<filter>", format = "application/json")]
 fn list<'a>(
     conn: DbConnection,
-    current_user: CurrentUser,
+    current_user: ScopedUser<scope::Read>,
     filter: ListFilter,
 ) -> ApiResult<ListResponse<'a>> {
     handle_list(conn, current_user, filter)
@@ -472,124 +839,579 @@ fn list<'a>(
 #[get("/", format = "application/json")]
 fn list_without_params<'a>(
     conn: DbConnection,
-    current_user: CurrentUser,
+    current_user: ScopedUser<scope::Read>,
 ) -> ApiResult<ListResponse<'a>> {
     handle_list(conn, current_user, ListFilter::default())
 }
 
-fn handle_list<'a>(
-    conn: DbConnection,
-    current_user: CurrentUser,
-    articles_filter: ListFilter,
-) -> ApiResult<ListResponse<'a>> {
-    use db::schema::*;
+/// Orders article lists can be sorted in. Each one walks an index on its
+/// key and `id`, see the `articles_sorting` migration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sort {
+    /// Most recently published first.
+    Newest,
+    Oldest,
+    /// Most favorited first.
+    Favorited,
+    /// Most commented first.
+    Commented,
+    /// Most recently updated first.
+    Updated,
+    /// Engagement weighed against age, see `Article::hot_score`.
+    Trending,
+}
 
-    let mut query = articles::table
-        .inner_join(users::table.on(articles::author_id.eq(users::id)))
-        .into_boxed::<Pg>();
-    if let Some(author) = articles_filter.author {
-        query = query.filter(users::username.eq(author));
+impl Sort {
+    fn from_param(sort: Option<String>) -> Result<Sort, ValidationError> {
+        let sort = match sort {
+            Some(sort) => sort,
+            None => return Ok(Sort::Newest),
+        };
+        match sort.as_str() {
+            "newest" => Ok(Sort::Newest),
+            "oldest" => Ok(Sort::Oldest),
+            "favorited" => Ok(Sort::Favorited),
+            "commented" => Ok(Sort::Commented),
+            "updated" => Ok(Sort::Updated),
+            "trending" => Ok(Sort::Trending),
+            _ => Err(ValidationError::from(
+                "sort",
+                format!("unknown sort order: {}", sort),
+            )),
+        }
     }
 
-    if let Some(favorited_by) = articles_filter.favorited {
-        let fav_articles = favorites::table
-            .select(favorites::article_id)
-            .filter(users::username.eq(favorited_by));
-        query = query.filter(articles::id.eq_any(fav_articles));
+    fn name(&self) -> &'static str {
+        match *self {
+            Sort::Newest => "newest",
+            Sort::Oldest => "oldest",
+            Sort::Favorited => "favorited",
+            Sort::Commented => "commented",
+            Sort::Updated => "updated",
+            Sort::Trending => "trending",
+        }
     }
 
-    if let Some(tag) = articles_filter.tag {
-        let cond = articles::tag_list.contains(vec![tag]);
-        query = query.filter(cond);
+    fn ascending(&self) -> bool {
+        *self == Sort::Oldest
     }
 
-    if let Some(offset) = articles_filter.offset {
-        query = query.offset(offset);
+    /// Cursor key of `article` in this order.
+    fn key(&self, article: &Article) -> String {
+        match *self {
+            Sort::Newest | Sort::Oldest => {
+                time_key(article.published_at.unwrap_or(article.created_at))
+            }
+            Sort::Updated => time_key(article.updated_at),
+            Sort::Favorited => article.favorites_count.to_string(),
+            Sort::Commented => article.comments_count.to_string(),
+            Sort::Trending => article.hot_score.to_string(),
+        }
+    }
+}
+
+/// Narrows `$query` to the articles past `($key, $id)` in `$column, id`
+/// order, ascending or descending.
+macro_rules! past_key {
+    ($query:expr, $column:expr, $key:expr, $id:expr, $ascending:expr) => {
+        if $ascending {
+            $query.filter($column.gt($key).or($column.eq($key).and(articles::id.gt($id))))
+        } else {
+            $query.filter($column.lt($key).or($column.eq($key).and(articles::id.lt($id))))
+        }
+    };
+}
+
+macro_rules! order_by {
+    ($query:expr, $column:expr, $ascending:expr) => {
+        if $ascending {
+            $query.order(($column.asc(), articles::id.asc()))
+        } else {
+            $query.order(($column.desc(), articles::id.desc()))
+        }
+    };
+}
+
+/// What a list of articles is narrowed down to, with usernames resolved.
+#[derive(Default, PartialEq)]
+struct ArticleFilter {
+    tags: Vec<String>,
+    all_tags: bool,
+    excluded_tags: Vec<String>,
+    author_ids: Option<Vec<i32>>,
+    excluded_author_ids: Vec<i32>,
+    favorited_by: Option<Vec<i32>>,
+    followed_by: Option<i32>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    updated_after: Option<DateTime<Utc>>,
+    updated_before: Option<DateTime<Utc>>,
+}
+
+impl ArticleFilter {
+    fn is_empty(&self) -> bool {
+        *self == ArticleFilter::default()
     }
 
-    let limit = articles_filter.limit.unwrap_or(20);
+    /// Listed articles matching the filter, in no particular order.
+    fn query<'a>(&self) -> articles::BoxedQuery<'a, Pg> {
+        let listed = vec![ArticleStatus::Published, ArticleStatus::Scheduled];
+        let mut query = articles::table
+            .filter(articles::status.eq(any(listed)))
+            .filter(articles::published_at.le(Utc::now()))
+            .into_boxed::<Pg>();
+        if let Some(ref author_ids) = self.author_ids {
+            query = query.filter(articles::author_id.eq(any(author_ids.clone())));
+        }
 
-    query = query.limit(limit);
+        if !self.excluded_author_ids.is_empty() {
+            let excluded = articles::author_id.eq(any(self.excluded_author_ids.clone()));
+            query = query.filter(not(excluded));
+        }
 
-    let articles = query.get_results::<(Article, User)>(&*conn)?;
-    let article_ids = articles.iter().map(|elem| elem.0.id).collect::<Vec<i32>>();
+        if let Some(ref favorited_by) = self.favorited_by {
+            let fav_articles = favorites::table
+                .select(favorites::article_id)
+                .filter(favorites::user_id.eq(any(favorited_by.clone())));
+            query = query.filter(articles::id.eq_any(fav_articles));
+        }
+
+        if let Some(followed_by) = self.followed_by {
+            let followed = followers::table
+                .select(followers::user_id)
+                .filter(followers::follower_id.eq(followed_by));
+            query = query.filter(articles::author_id.eq_any(followed));
+        }
+
+        if !self.tags.is_empty() {
+            query = if self.all_tags {
+                query.filter(articles::tag_list.contains(self.tags.clone()))
+            } else {
+                query.filter(articles::tag_list.overlaps_with(self.tags.clone()))
+            };
+        }
+
+        if !self.excluded_tags.is_empty() {
+            let excluded = articles::tag_list.overlaps_with(self.excluded_tags.clone());
+            query = query.filter(not(excluded));
+        }
+
+        if let Some(created_after) = self.created_after {
+            query = query.filter(articles::created_at.ge(created_after));
+        }
+        if let Some(created_before) = self.created_before {
+            query = query.filter(articles::created_at.lt(created_before));
+        }
+        if let Some(updated_after) = self.updated_after {
+            query = query.filter(articles::updated_at.ge(updated_after));
+        }
+        if let Some(updated_before) = self.updated_before {
+            query = query.filter(articles::updated_at.lt(updated_before));
+        }
+        query
+    }
+
+    /// Number of matching articles, and whether it is an estimate. Counting
+    /// stops past `CONFIG.list_count_limit`, beyond it the planner's row
+    /// estimate stands in for an unfiltered list and the limit for others.
+    fn count(&self, conn: &PgConnection) -> Result<(i64, bool), ApiError> {
+        let limit = CONFIG.list_count_limit;
+        let matching = self.query().select(articles::id).limit(limit + 1);
+        let count = articles::table
+            .filter(articles::id.eq_any(matching))
+            .count()
+            .get_result::<i64>(conn)?;
+        if count <= limit {
+            return Ok((count, false));
+        }
+
+        if self.is_empty() {
+            let estimate = sql_query(
+                "SELECT reltuples::BIGINT AS count FROM pg_class WHERE oid = 'articles'::regclass",
+            ).get_result::<Count>(conn)?;
+            Ok((max(estimate.count, count), true))
+        } else {
+            Ok((count, true))
+        }
+    }
+
+    /// One page of matching articles in `sort` order, with cursors to the
+    /// pages around it.
+    fn page(
+        &self,
+        page: &Page,
+        sort: Sort,
+        conn: &PgConnection,
+    ) -> Result<(Vec<Article>, Option<String>, Option<String>), ApiError> {
+        // Rows are fetched in index order, against list order when paging
+        // backwards.
+        let ascending = sort.ascending() != page.backwards();
+        let mut query = self.query();
+        if let Some(ref cursor) = page.cursor {
+            if cursor.order != sort.name() {
+                return Err(invalid_cursor().into());
+            }
+            let id = cursor.id;
+            query = match sort {
+                Sort::Newest | Sort::Oldest => {
+                    let key = parse_time_key(&cursor.key)?;
+                    past_key!(query, articles::published_at, key, id, ascending)
+                }
+                Sort::Updated => {
+                    let key = parse_time_key(&cursor.key)?;
+                    past_key!(query, articles::updated_at, key, id, ascending)
+                }
+                Sort::Favorited => {
+                    let key = cursor.key.parse::<i32>().map_err(|_| invalid_cursor())?;
+                    past_key!(query, articles::favorites_count, key, id, ascending)
+                }
+                Sort::Commented => {
+                    let key = cursor.key.parse::<i32>().map_err(|_| invalid_cursor())?;
+                    past_key!(query, articles::comments_count, key, id, ascending)
+                }
+                Sort::Trending => {
+                    let key = cursor.key.parse::<f64>().map_err(|_| invalid_cursor())?;
+                    past_key!(query, articles::hot_score, key, id, ascending)
+                }
+            };
+        }
+
+        query = match sort {
+            Sort::Newest | Sort::Oldest => order_by!(query, articles::published_at, ascending),
+            Sort::Updated => order_by!(query, articles::updated_at, ascending),
+            Sort::Favorited => order_by!(query, articles::favorites_count, ascending),
+            Sort::Commented => order_by!(query, articles::comments_count, ascending),
+            Sort::Trending => order_by!(query, articles::hot_score, ascending),
+        };
+
+        // One extra row tells whether there is anything past this page.
+        let mut articles = query
+            .offset(page.offset)
+            .limit(page.limit + 1)
+            .load::<Article>(conn)?;
+        let more = articles.len() as i64 > page.limit;
+        articles.truncate(page.limit as usize);
+
+        let (has_next, has_prev) = if page.backwards() {
+            articles.reverse();
+            (true, more)
+        } else {
+            (more, page.cursor.is_some() || page.offset > 0)
+        };
+        let cursor = |direction, article: &Article| {
+            Cursor::new(direction, sort.name(), sort.key(article), article.id).encode()
+        };
+        let next_cursor = match articles.last() {
+            Some(last) if has_next => Some(cursor(Direction::Next, last)),
+            _ => None,
+        };
+        let prev_cursor = match articles.first() {
+            Some(first) if has_prev => Some(cursor(Direction::Prev, first)),
+            _ => None,
+        };
+        Ok((articles, next_cursor, prev_cursor))
+    }
+
+    fn list<'a>(
+        &self,
+        page: &Page,
+        sort: Sort,
+        current_user: Option<&User>,
+        conn: &PgConnection,
+    ) -> ApiResult<ListResponse<'a>> {
+        let (articles_count, articles_count_estimated) = self.count(conn)?;
+        let (articles, next_cursor, prev_cursor) = self.page(page, sort, conn)?;
+        Ok(Json(ListResponse {
+            articles: enrich(articles, current_user, conn)?,
+            articles_count,
+            articles_count_estimated,
+            next_cursor,
+            prev_cursor,
+        }))
+    }
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+/// Adds authors, favorite counts and whether `current_user` favorited the
+/// article or follows its author.
+fn enrich<'a>(
+    articles: Vec<Article>,
+    current_user: Option<&User>,
+    conn: &PgConnection,
+) -> Result<Vec<RichArticle<'a>>, ApiError> {
+    let article_ids = articles.iter().map(|article| article.id).collect::<Vec<i32>>();
+    let author_ids = articles
+        .iter()
+        .map(|article| article.author_id)
+        .collect::<Vec<i32>>();
+
+    let authors = users::table
+        .filter(users::id.eq(any(author_ids.clone())))
+        .get_results::<User>(conn)?
+        .into_iter()
+        .map(|user| (user.id, user))
+        .collect::<HashMap<_, _>>();
 
     let mut fav_count = favorites::table
         .select(sql::<(Integer, BigInt)>("article_id, count(user_id)"))
         .group_by(favorites::article_id)
         .filter(favorites::article_id.eq(any(article_ids.clone())))
-        .get_results::<(i32, i64)>(&*conn)?
+        .get_results::<(i32, i64)>(conn)?
         .into_iter()
         .collect::<HashMap<_, _>>();
-    match current_user {
-        Ok(user) => {
-            let authors = articles.iter().map(|elem| elem.1.id).collect::<Vec<i32>>();
-            let follows = exists(
-                followers::table.select(sql::<Integer>("1")).filter(
-                    followers::follower_id
-                        .eq(user.id)
-                        .and(users::id.eq(followers::user_id)),
-                ),
-            );
-
-            let mut follows = users::table
-                .select((users::id, follows))
-                .filter(users::id.eq(any(authors)))
-                .get_results::<(i32, bool)>(&*conn)?
-                .into_iter()
-                .collect::<HashMap<_, _>>();
-
-            let favorited = exists(
-                favorites::table.select(sql::<Integer>("1")).filter(
-                    favorites::user_id
-                        .eq(user.id)
-                        .and(articles::id.eq(favorites::article_id)),
-                ),
-            );
-
-            let mut favorited = articles::table
-                .select((articles::id, favorited))
-                .filter(articles::id.eq(any(article_ids.clone())))
-                .get_results::<(i32, bool)>(&*conn)?
-                .into_iter()
-                .collect::<HashMap<_, _>>();
-
-            let rich_articles = articles
-                .into_iter()
-                .map(|elem| {
-                    let article = elem.0;
-                    let user = elem.1;
-                    let follows_user = follows.remove(&user.id).unwrap_or(false);
-                    let favorites_count = fav_count.remove(&article.id).unwrap_or(0);
-                    let favorited_by_user = favorited.remove(&article.id).unwrap_or(false);
-                    let profile = user.profile(follows_user);
-                    RichArticle::from(article, profile, Some(favorites_count), favorited_by_user)
-                })
-                .collect::<Vec<RichArticle>>();
-            let count = rich_articles.len();
-            Ok(Json(ListResponse {
-                articles: rich_articles,
-                articles_count: count,
-            }))
-        }
-        Err(_) => {
-            let rich_articles = articles
-                .into_iter()
-                .map(|elem| {
-                    let article = elem.0;
-                    let user = elem.1;
-                    let favorites_count = fav_count.remove(&article.id).unwrap_or(0);
-                    let profile = user.profile(false);
-                    RichArticle::from(article, profile, Some(favorites_count), false)
-                })
-                .collect::<Vec<RichArticle>>();
-            let count = rich_articles.len();
-            Ok(Json(ListResponse {
-                articles: rich_articles,
-                articles_count: count,
-            }))
+
+    let (favorited, following) = match current_user {
+        Some(user) => {
+            let favorited = favorites::table
+                .select(favorites::article_id)
+                .filter(favorites::user_id.eq(user.id))
+                .filter(favorites::article_id.eq(any(article_ids)))
+                .get_results::<i32>(conn)?;
+            let following = followers::table
+                .select(followers::user_id)
+                .filter(followers::follower_id.eq(user.id))
+                .filter(followers::user_id.eq(any(author_ids)))
+                .get_results::<i32>(conn)?;
+            (
+                favorited.into_iter().collect::<HashSet<_>>(),
+                following.into_iter().collect::<HashSet<_>>(),
+            )
+        }
+        None => (HashSet::new(), HashSet::new()),
+    };
+
+    Ok(articles
+        .into_iter()
+        .filter_map(|article| {
+            let author = authors.get(&article.author_id)?;
+            let profile = author.profile(following.contains(&author.id));
+            let favorites_count = fav_count.remove(&article.id).unwrap_or(0);
+            let favorited_by_user = favorited.contains(&article.id);
+            Some(RichArticle::from(
+                article,
+                profile,
+                Some(favorites_count),
+                favorited_by_user,
+            ))
+        })
+        .collect())
+}
+
+fn handle_list<'a>(
+    conn: DbConnection,
+    current_user: ScopedUser<scope::Read>,
+    articles_filter: ListFilter,
+) -> ApiResult<ListResponse<'a>> {
+    let current_user = optional_user(current_user)?;
+    let mut errors = articles_filter.errors;
+    let ranges = [
+        ("created_before", articles_filter.created_after, articles_filter.created_before),
+        ("updated_before", articles_filter.updated_after, articles_filter.updated_before),
+    ];
+    for &(key, after, before) in ranges.iter() {
+        if let (Some(after), Some(before)) = (after, before) {
+            if after >= before {
+                errors.add_error(key, "range ends before it starts");
+            }
+        }
+    }
+    let page = Page::new(
+        articles_filter.limit,
+        articles_filter.offset,
+        articles_filter.cursor,
+    );
+    let sort = Sort::from_param(articles_filter.sort);
+    let (page, sort) = match (page, sort) {
+        (Ok(page), Ok(sort)) if errors.empty() => (page, sort),
+        (page, sort) => {
+            errors.merge(page.err().unwrap_or_default());
+            errors.merge(sort.err().unwrap_or_default());
+            return Err(errors.into());
+        }
+    };
+
+    let mut filter = ArticleFilter {
+        tags: articles_filter.tag,
+        all_tags: articles_filter.all_tags,
+        excluded_tags: articles_filter.excluded_tags,
+        created_after: articles_filter.created_after,
+        created_before: articles_filter.created_before,
+        updated_after: articles_filter.updated_after,
+        updated_before: articles_filter.updated_before,
+        ..ArticleFilter::default()
+    };
+    // Names are resolved up front so that former usernames keep matching.
+    // Unknown names match nothing, so an unknown author excludes nobody.
+    if !articles_filter.author.is_empty() {
+        let author_ids = user_ids(&articles_filter.author, &*conn)?;
+        if author_ids.is_empty() {
+            return Ok(Json(ListResponse::empty()));
+        }
+        filter.author_ids = Some(author_ids);
+    }
+
+    filter.excluded_author_ids = user_ids(&articles_filter.excluded_authors, &*conn)?;
+
+    if !articles_filter.favorited.is_empty() {
+        let favorited_by = user_ids(&articles_filter.favorited, &*conn)?;
+        if favorited_by.is_empty() {
+            return Ok(Json(ListResponse::empty()));
+        }
+        filter.favorited_by = Some(favorited_by);
+    }
+
+    if let Some(followed_by) = articles_filter.followed_by {
+        match User::find_by_any_name(&followed_by, &*conn)? {
+            Some(user) => filter.followed_by = Some(user.id),
+            None => return Ok(Json(ListResponse::empty())),
         }
     }
+
+    filter.list(&page, sort, current_user.as_ref(), &*conn)
+}
+
+/// Ids of the users going or having gone by `names`, skipping unknown ones.
+fn user_ids(names: &[String], conn: &PgConnection) -> Result<Vec<i32>, ApiError> {
+    let mut ids = Vec::new();
+    for name in names {
+        if let Some(user) = User::find_by_any_name(name, conn)? {
+            ids.push(user.id);
+        }
+    }
+    Ok(ids)
+}
+
+#[derive(FromForm, Debug)]
+struct SearchParams {
+    q: String,
+    /// Text search configuration to stem the query with, only articles
+    /// written in it are searched.
+    lang: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Turns a search string into `to_tsquery` syntax. Quoted parts become
+/// phrases, a trailing `*` matches any word starting with the term and a
+/// leading `-` excludes it. Anything but letters and digits only separates
+/// words, so user input can not inject query operators.
+fn tsquery(q: &str) -> String {
+    fn words(s: &str) -> Vec<&str> {
+        s.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect()
+    }
+
+    let mut terms = Vec::new();
+    for (i, part) in q.split('"').enumerate() {
+        if i % 2 == 1 {
+            let phrase = words(part);
+            if !phrase.is_empty() {
+                terms.push(format!("({})", phrase.join(" <-> ")));
+            }
+            continue;
+        }
+
+        for term in part.split_whitespace() {
+            let excluded = term.starts_with('-');
+            let prefix = term.ends_with('*');
+            let phrase = words(term);
+            if phrase.is_empty() {
+                continue;
+            }
+            let mut term = phrase.join(" <-> ");
+            if prefix {
+                term.push_str(":*");
+            }
+            if phrase.len() > 1 {
+                term = format!("({})", term);
+            }
+            if excluded {
+                term = format!("!{}", term);
+            }
+            terms.push(term);
+        }
+    }
+    terms.join(" & ")
+}
+
+const SEARCH_QUERY: &str = "
+    SELECT articles.id, articles.slug, articles.title, articles.description, articles.body,
+        articles.tag_list, articles.created_at, articles.updated_at, articles.language,
+        'published'::TEXT AS status, articles.published_at,
+        (SELECT count(*) FROM favorites WHERE favorites.article_id = articles.id)
+            AS favorites_count,
+        EXISTS(SELECT 1 FROM favorites
+            WHERE favorites.article_id = articles.id AND favorites.user_id = $3) AS favorited,
+        users.username, users.bio, users.image,
+        EXISTS(SELECT 1 FROM followers
+            WHERE followers.user_id = users.id AND followers.follower_id = $3) AS following,
+        ts_rank_cd(articles.search_vector, query) AS rank,
+        ts_headline($1::regconfig, html_escape(articles.title), query,
+            'HighlightAll=true, StartSel=<mark>, StopSel=</mark>') AS title_highlight,
+        ts_headline($1::regconfig, html_escape(articles.body), query,
+            'MaxFragments=2, MaxWords=30, MinWords=10, StartSel=<mark>, StopSel=</mark>')
+            AS snippet
+    FROM articles
+    INNER JOIN users ON users.id = articles.author_id
+    CROSS JOIN to_tsquery($1::regconfig, $2) query
+    WHERE articles.language = $1 AND articles.search_vector @@ query
+        AND articles.status IN ('published', 'scheduled') AND articles.published_at <= now()
+    ORDER BY rank DESC, articles.id DESC
+    LIMIT $4 OFFSET $5";
+
+const SEARCH_COUNT_QUERY: &str = "
+    SELECT count(*) AS count FROM articles
+    WHERE articles.language = $1 AND articles.search_vector @@ to_tsquery($1::regconfig, $2)
+        AND articles.status IN ('published', 'scheduled') AND articles.published_at <= now()";
+
+#[get("/search?<params>", format = "application/json")]
+fn search<'a>(
+    conn: DbConnection,
+    current_user: ScopedUser<scope::Read>,
+    params: SearchParams,
+) -> ApiResult<ListResponse<'a>> {
+    let language = params
+        .lang
+        .unwrap_or_else(|| CONFIG.search_language.clone());
+    if !is_search_language(&language, &*conn)? {
+        let error = ValidationError::from("lang", format!("unknown language: {}", language));
+        return Err(error.into());
+    }
+
+    let query = tsquery(&params.q);
+    if query.is_empty() {
+        return Err(ValidationError::from("q", "empty search").into());
+    }
+    // Ranked results have no stable key to hang a cursor on.
+    let page = Page::new(params.limit, params.offset, None)?;
+
+    let current_user_id = optional_user(current_user)?.map(|user| user.id);
+    let articles_count = sql_query(SEARCH_COUNT_QUERY)
+        .bind::<Text, _>(&language)
+        .bind::<Text, _>(&query)
+        .get_result::<Count>(&*conn)?
+        .count;
+    let articles = sql_query(SEARCH_QUERY)
+        .bind::<Text, _>(&language)
+        .bind::<Text, _>(&query)
+        .bind::<Nullable<Integer>, _>(current_user_id)
+        .bind::<BigInt, _>(page.limit)
+        .bind::<BigInt, _>(page.offset)
+        .load::<RichArticle>(&*conn)?;
+
+    Ok(Json(ListResponse {
+        articles,
+        articles_count,
+        articles_count_estimated: false,
+        next_cursor: None,
+        prev_cursor: None,
+    }))
 }
 
 #[derive(Debug, Serialize)]
@@ -602,8 +1424,11 @@ sql_function!(unnest, unnest_t, (a: Array<Text>) -> Text);
 #[get("/tags", format = "application/json")]
 fn tags(conn: DbConnection) -> ApiResult<TagList> {
     use db::schema::articles::dsl::*;
+    let listed = vec![ArticleStatus::Published, ArticleStatus::Scheduled];
     let tags = articles
         .select(unnest(tag_list))
+        .filter(status.eq(any(listed)))
+        .filter(published_at.le(Utc::now()))
         .distinct()
         .get_results::<String>(&*conn)?;
     Ok(Json(TagList { tags }))
@@ -613,23 +1438,25 @@ fn tags(conn: DbConnection) -> ApiResult<TagList> {
 struct Pagination {
     limit: Option<i64>,
     offset: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<String>,
 }
 
 #[get("/feed?<pagination>", format = "application/json")]
 fn feed(
     conn: DbConnection,
-    current_user: Result<User, ApiError>,
+    current_user: ScopedUser<scope::Read>,
     pagination: Pagination,
 ) -> ApiResult<ListResponse<'static>> {
-    handle_feed(current_user?, conn, pagination)
+    handle_feed(current_user?.user, conn, pagination)
 }
 
 #[get("/feed", format = "application/json")]
 fn feed_without_params(
     conn: DbConnection,
-    current_user: Result<User, ApiError>,
+    current_user: ScopedUser<scope::Read>,
 ) -> ApiResult<ListResponse<'static>> {
-    handle_feed(current_user?, conn, Pagination::default())
+    handle_feed(current_user?.user, conn, Pagination::default())
 }
 
 fn handle_feed(
@@ -637,56 +1464,124 @@ fn handle_feed(
     conn: DbConnection,
     pagination: Pagination,
 ) -> ApiResult<ListResponse<'static>> {
+    let page = Page::new(pagination.limit, pagination.offset, pagination.cursor)?;
+    let sort = Sort::from_param(pagination.sort)?;
     let following = followers::table
         .select(followers::user_id)
         .filter(followers::follower_id.eq(&current_user.id))
         .get_results::<i32>(&*conn)?;
 
-    let query = articles::table
-        .inner_join(users::table.on(articles::author_id.eq(users::id)))
-        .filter(articles::author_id.eq_any(following))
-        .offset(pagination.offset.unwrap_or(0))
-        .limit(pagination.limit.unwrap_or(20));
+    let filter = ArticleFilter {
+        author_ids: Some(following),
+        ..ArticleFilter::default()
+    };
+    filter.list(&page, sort, Some(&current_user), &*conn)
+}
 
-    let data = query.get_results::<(Article, User)>(&*conn)?;
-    let article_ids = data.iter().map(|elem| elem.0.id).collect::<Vec<i32>>();
+#[get("/user/drafts?<pagination>", format = "application/json")]
+fn drafts(
+    conn: DbConnection,
+    current_user: CurrentUser,
+    pagination: Pagination,
+) -> ApiResult<ListResponse<'static>> {
+    handle_drafts(current_user?, conn, pagination)
+}
 
-    let mut fav_count = favorites::table
-        .select(sql::<(Integer, BigInt)>("article_id, count(user_id)"))
-        .group_by(favorites::article_id)
-        .filter(favorites::article_id.eq(any(article_ids.clone())))
-        .get_results::<(i32, i64)>(&*conn)?
-        .into_iter()
-        .collect::<HashMap<_, _>>();
+#[get("/user/drafts", format = "application/json")]
+fn drafts_without_params(
+    conn: DbConnection,
+    current_user: CurrentUser,
+) -> ApiResult<ListResponse<'static>> {
+    handle_drafts(current_user?, conn, Pagination::default())
+}
 
-    let favorited = exists(
-        favorites::table.select(sql::<Integer>("1")).filter(
-            favorites::user_id
-                .eq(current_user.id)
-                .and(articles::id.eq(favorites::article_id)),
-        ),
-    );
+/// The current user's drafts and articles scheduled for later, most
+/// recently edited first. Only offset paging is supported, a cursor or sort
+/// order is refused rather than ignored.
+fn handle_drafts(
+    current_user: User,
+    conn: DbConnection,
+    pagination: Pagination,
+) -> ApiResult<ListResponse<'static>> {
+    let mut errors = ValidationError::default();
+    if pagination.cursor.is_some() {
+        errors.add_error("cursor", "drafts can only be paged with offset");
+    }
+    if pagination.sort.is_some() {
+        errors.add_error("sort", "drafts are always sorted by last edit");
+    }
+    let page = match Page::new(pagination.limit, pagination.offset, None) {
+        Ok(page) if errors.empty() => page,
+        Ok(_) => return Err(errors.into()),
+        Err(error) => {
+            errors.merge(error);
+            return Err(errors.into());
+        }
+    };
+    let now = Utc::now();
+    let unpublished = || {
+        let draft = articles::status.eq(ArticleStatus::Draft);
+        let scheduled = articles::status
+            .eq(ArticleStatus::Scheduled)
+            .and(articles::published_at.gt(now));
+        articles::table
+            .filter(articles::author_id.eq(current_user.id))
+            .filter(draft.or(scheduled))
+    };
 
-    let mut favorited = articles::table
-        .select((articles::id, favorited))
-        .filter(articles::id.eq(any(article_ids.clone())))
-        .get_results::<(i32, bool)>(&*conn)?
-        .into_iter()
-        .collect::<HashMap<_, _>>();
+    let articles_count = unpublished().count().get_result::<i64>(&*conn)?;
+    let articles = unpublished()
+        .order((articles::updated_at.desc(), articles::id.desc()))
+        .offset(page.offset)
+        .limit(page.limit)
+        .load::<Article>(&*conn)?;
 
-    let rich_articles = data.into_iter()
-        .map(|elem| {
-            let article = elem.0;
-            let user = elem.1;
-            let favorites_count = fav_count.remove(&article.id).unwrap_or(0);
-            let favorited_by_user = favorited.remove(&article.id).unwrap_or(false);
-            let profile = user.profile(true);
-            RichArticle::from(article, profile, Some(favorites_count), favorited_by_user)
-        })
-        .collect::<Vec<RichArticle>>();
-    let count = rich_articles.len();
     Ok(Json(ListResponse {
-        articles: rich_articles,
-        articles_count: count,
+        articles: enrich(articles, Some(&current_user), &*conn)?,
+        articles_count,
+        articles_count_estimated: false,
+        next_cursor: None,
+        prev_cursor: None,
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::tsquery;
+
+    #[test]
+    fn tsquery_joins_words_with_and() {
+        assert_eq!(tsquery("rust  diesel"), "rust & diesel");
+    }
+
+    #[test]
+    fn tsquery_keeps_quoted_phrases_together() {
+        assert_eq!(
+            tsquery("\"web framework\" rust"),
+            "(web <-> framework) & rust"
+        );
+        assert_eq!(tsquery("\"unclosed phrase"), "(unclosed <-> phrase)");
+    }
+
+    #[test]
+    fn tsquery_matches_prefixes() {
+        assert_eq!(tsquery("rock*"), "rock:*");
+        assert_eq!(tsquery("rock-n*"), "(rock <-> n:*)");
+    }
+
+    #[test]
+    fn tsquery_excludes_negated_terms() {
+        assert_eq!(tsquery("rust -java"), "rust & !java");
+        assert_eq!(tsquery("-rock*"), "!rock:*");
+    }
+
+    #[test]
+    fn tsquery_drops_query_operators() {
+        assert_eq!(
+            tsquery("foo'bar & !baz | qux:A <-> (quux)"),
+            "(foo <-> bar) & baz & (qux <-> A) & quux"
+        );
+        assert_eq!(tsquery(" & | ! <-> ( ) "), "");
+        assert_eq!(tsquery("\"\""), "");
+    }
+}
@@ -1,8 +1,8 @@
 use chrono::{DateTime, Utc};
-use db::schema::{comments, followers, users};
+use db::schema::{articles, comments, followers, users};
 use db::DbConnection;
 use users::models::User;
-use users::CurrentUser;
+use users::{optional_user, ScopedUser};
 use article::Article;
 use rocket_contrib::Json;
 use types::{ApiError, ApiResult};
@@ -11,6 +11,7 @@ use diesel::prelude::*;
 use utils::serialize_date;
 use serde::de::Deserialize;
 use std::fmt::Debug;
+use policy::{self, scope, Permission};
 use profile::Profile;
 use diesel::BelongingToDsl;
 use diesel::{delete as diesel_delete, select};
@@ -20,6 +21,7 @@ use std::collections::HashMap;
 use std::convert::From;
 
 allow_tables_to_appear_in_same_query!(comments, users);
+allow_tables_to_appear_in_same_query!(comments, articles);
 
 #[derive(Debug, Serialize, Associations, PartialEq, AsChangeset, Identifiable, Queryable)]
 #[belongs_to(Article)]
@@ -90,13 +92,15 @@ pub struct CommentsContainer<T> {
 #[post("/<slug>/comments", data = "<details>", format = "application/json")]
 pub fn add(
     conn: DbConnection,
-    user: CurrentUser,
+    user: ScopedUser<scope::CommentsWrite>,
     slug: String,
     details: Json<CommentContainer<CommentBody>>,
 ) -> ApiResult<CommentContainer<CommentView<'static>>> {
     let details = details.into_inner();
-    let article = Article::load_by_slug(&slug, &*conn)?;
-    let user = user?;
+    let user = user?.user;
+    let article = Article::load_readable(&slug, Some(&user), &*conn)?;
+    policy::authorize(&user, Permission::CreateComment, None)?;
+    user.require_verified()?;
     let now = Utc::now();
     let new_comment = NewComment {
         article_id: article.id,
@@ -121,16 +125,17 @@ pub fn add(
 #[get("/<slug>/comments", format = "application/json")]
 fn get(
     conn: DbConnection,
-    user: CurrentUser,
+    user: ScopedUser<scope::Read>,
     slug: String,
 ) -> ApiResult<CommentsContainer<Vec<CommentView<'static>>>> {
-    let article = Article::load_by_slug(&slug, &conn)?;
+    let user = optional_user(user)?;
+    let article = Article::load_readable(&slug, user.as_ref(), &conn)?;
     let data = Comment::belonging_to(&article)
         .inner_join(users::table.on(comments::user_id.eq(users::id)))
         .get_results::<(Comment, User)>(&*conn)?;
 
     match user {
-        Ok(user) => {
+        Some(user) => {
             let authors = data.iter().map(|elem| elem.1.id).collect::<Vec<i32>>();
             let follows = exists(
                 followers::table.select(sql::<Integer>("1")).filter(
@@ -157,7 +162,7 @@ fn get(
                 comments: comments.collect(),
             }))
         }
-        Err(_) => {
+        None => {
             let comments = data.into_iter().map(|elem| {
                 let comment = elem.0;
                 let author = elem.1;
@@ -172,12 +177,15 @@ fn get(
 }
 
 #[delete("/<_slug>/comments/<id>", format = "application/json")]
-fn delete(conn: DbConnection, user: CurrentUser, _slug: String, id: i32) -> ApiResult<()> {
-    let user = user?;
+fn delete(
+    conn: DbConnection,
+    user: ScopedUser<scope::CommentsWrite>,
+    _slug: String,
+    id: i32,
+) -> ApiResult<()> {
+    let user = user?.user;
     let comment = comments::table.find(id).first::<Comment>(&*conn)?;
-    if comment.user_id != user.id {
-        return Err(ApiError::Forbidden);
-    }
+    policy::authorize(&user, Permission::DeleteComment, Some(comment.user_id))?;
     diesel_delete(&comment).execute(&*conn)?;
     Ok(Json(()))
 }
@@ -0,0 +1,276 @@
+use dotenv::dotenv;
+use std::collections::HashMap;
+use std::env;
+use std::str::FromStr;
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::from_env();
+}
+
+pub struct Config {
+    pub jwt: JwtConfig,
+    pub password: PasswordConfig,
+    pub username: UsernameConfig,
+    pub login_throttle: LoginThrottleConfig,
+    pub mail: MailConfig,
+    /// External identity providers users can sign in with, by name.
+    pub oidc_providers: HashMap<String, OidcProvider>,
+    /// Issuer shown by authenticator apps next to the account.
+    pub totp_issuer: String,
+    /// Text search configuration articles are written in unless they say
+    /// otherwise, and searches use unless they ask for another one.
+    pub search_language: String,
+    /// Article lists count their matches exactly up to this many, and
+    /// estimate beyond.
+    pub list_count_limit: i64,
+}
+
+impl Config {
+    fn from_env() -> Config {
+        dotenv().ok();
+        Config {
+            jwt: JwtConfig::from_env(),
+            password: PasswordConfig::from_env(),
+            username: UsernameConfig::from_env(),
+            login_throttle: LoginThrottleConfig::from_env(),
+            mail: MailConfig::from_env(),
+            oidc_providers: oidc_providers_from_env(),
+            totp_issuer: var_or("TOTP_ISSUER", "Conduit".to_string()),
+            search_language: var_or("SEARCH_LANGUAGE", "english".to_string()),
+            list_count_limit: var_or("LIST_COUNT_LIMIT", 10000),
+        }
+    }
+}
+
+pub struct JwtConfig {
+    /// Key id (`kid` header) used to sign newly issued tokens.
+    pub current_kid: String,
+    /// Every key still accepted for verification, by key id. Rotating keys
+    /// means adding a new one, switching `JWT_KID` to it and dropping the
+    /// old one once its tokens have expired.
+    pub keys: HashMap<String, String>,
+    /// Lifetime of an access token in seconds.
+    pub ttl: u64,
+    /// Lifetime of a refresh token in seconds.
+    pub refresh_ttl: u64,
+}
+
+impl JwtConfig {
+    fn from_env() -> JwtConfig {
+        let keys = env::var("JWT_KEYS")
+            .expect("JWT_KEYS must be set")
+            .split(',')
+            .filter_map(|pair| {
+                let mut parts = pair.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(kid), Some(secret)) if !secret.trim().is_empty() => {
+                        Some((kid.trim().to_string(), secret.trim().to_string()))
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<HashMap<_, _>>();
+
+        let current_kid = env::var("JWT_KID").expect("JWT_KID must be set");
+        if !keys.contains_key(&current_kid) {
+            panic!("JWT_KID {} is not one of JWT_KEYS", current_kid);
+        }
+
+        JwtConfig {
+            current_kid,
+            keys,
+            ttl: var_or("JWT_TTL", 900),
+            refresh_ttl: var_or("REFRESH_TOKEN_TTL", 30 * 24 * 3600),
+        }
+    }
+
+    pub fn signing_key(&self) -> &str {
+        &self.keys[&self.current_kid]
+    }
+
+    pub fn key(&self, kid: &str) -> Option<&str> {
+        self.keys.get(kid).map(|key| key.as_str())
+    }
+}
+
+pub struct PasswordConfig {
+    /// Argon2id memory cost in KiB.
+    pub argon2_mem_cost: u32,
+    /// Argon2id number of passes.
+    pub argon2_time_cost: u32,
+    /// Argon2id degree of parallelism.
+    pub argon2_lanes: u32,
+    /// Lifetime of a password reset token in seconds.
+    pub reset_ttl: i64,
+    /// Shortest accepted password, counted in characters.
+    pub min_length: usize,
+    /// Longest accepted password, counted in bytes. Keeps hashing and the
+    /// strength estimate cheap.
+    pub max_length: usize,
+    /// Lowest accepted zxcvbn score, from 0 (trivial) to 4 (very strong).
+    pub min_score: u8,
+    /// Directory of breached password hashes in the format of the Pwned
+    /// Passwords range API: one file per uppercase 5 character SHA-1 prefix,
+    /// holding `SUFFIX:COUNT` lines. The check is skipped when unset.
+    pub breached_dir: Option<String>,
+}
+
+impl PasswordConfig {
+    fn from_env() -> PasswordConfig {
+        PasswordConfig {
+            argon2_mem_cost: var_or("ARGON2_MEM_COST", 19456),
+            argon2_time_cost: var_or("ARGON2_TIME_COST", 2),
+            argon2_lanes: var_or("ARGON2_LANES", 1),
+            reset_ttl: var_or("PASSWORD_RESET_TTL", 3600),
+            min_length: var_or("PASSWORD_MIN_LENGTH", 10),
+            max_length: var_or("PASSWORD_MAX_LENGTH", 128),
+            min_score: var_or("PASSWORD_MIN_SCORE", 3),
+            breached_dir: env::var("PASSWORD_BREACHED_DIR").ok(),
+        }
+    }
+}
+
+pub struct UsernameConfig {
+    /// Bounds on the length of a username, counted in characters.
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Names nobody may register, nor anything confusable with them.
+    pub reserved: Vec<String>,
+    /// Days a name given up in a rename stays blocked for everyone but its
+    /// previous owner.
+    pub reuse_cooldown_days: i64,
+}
+
+const DEFAULT_RESERVED_USERNAMES: &str = "admin,administrator,api,settings,login,logout,register,\
+                                          editor,profile,profiles,article,articles,user,users,\
+                                          root,support,help,about,security,system,null,undefined";
+
+impl UsernameConfig {
+    fn from_env() -> UsernameConfig {
+        UsernameConfig {
+            min_length: var_or("USERNAME_MIN_LENGTH", 3),
+            max_length: var_or("USERNAME_MAX_LENGTH", 32),
+            reserved: var_or("USERNAME_RESERVED", DEFAULT_RESERVED_USERNAMES.to_string())
+                .split(',')
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            reuse_cooldown_days: var_or("USERNAME_REUSE_COOLDOWN_DAYS", 30),
+        }
+    }
+}
+
+pub struct LoginThrottleConfig {
+    /// Failed logins for one account from one client address before that
+    /// pair gets locked. Failures from all addresses together slow the
+    /// account down after as many, but never for longer than
+    /// `account_max_delay`.
+    pub account_threshold: i32,
+    /// Failed logins from one client address before it gets locked.
+    pub ip_threshold: i32,
+    /// Verification and reset mails for one address before further requests
+    /// are refused.
+    pub mail_threshold: i32,
+    /// First lockout in seconds, doubled on every further failure.
+    pub base_lockout: i64,
+    /// Upper bound for a single lockout in seconds.
+    pub max_lockout: i64,
+    /// Upper bound in seconds for how long an account is slowed down.
+    pub account_max_delay: i64,
+    /// Seconds without failures after which the counter starts over.
+    pub window: i64,
+}
+
+impl LoginThrottleConfig {
+    fn from_env() -> LoginThrottleConfig {
+        LoginThrottleConfig {
+            account_threshold: var_or("LOGIN_ACCOUNT_THRESHOLD", 5),
+            ip_threshold: var_or("LOGIN_IP_THRESHOLD", 20),
+            mail_threshold: var_or("MAIL_THRESHOLD", 3),
+            base_lockout: var_or("LOGIN_BASE_LOCKOUT", 30),
+            max_lockout: var_or("LOGIN_MAX_LOCKOUT", 3600),
+            account_max_delay: var_or("LOGIN_ACCOUNT_MAX_DELAY", 60),
+            window: var_or("LOGIN_FAILURE_WINDOW", 900),
+        }
+    }
+}
+
+pub struct MailConfig {
+    /// One of `smtp`, `file` or `log`. `log` never delivers anything and
+    /// has to be picked explicitly.
+    pub transport: String,
+    pub from: String,
+    pub smtp_host: String,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// File the `file` transport appends messages to.
+    pub sink_path: String,
+    /// Frontend base url that links in mails point to.
+    pub app_url: String,
+    /// Lifetime of an email verification token in seconds.
+    pub verification_ttl: u64,
+}
+
+impl MailConfig {
+    fn from_env() -> MailConfig {
+        MailConfig {
+            transport: var_or("MAIL_TRANSPORT", "smtp".to_string()),
+            from: var_or("MAIL_FROM", "conduit@localhost".to_string()),
+            smtp_host: var_or("SMTP_HOST", "localhost".to_string()),
+            smtp_username: env::var("SMTP_USERNAME").ok(),
+            smtp_password: env::var("SMTP_PASSWORD").ok(),
+            sink_path: var_or("MAIL_SINK_PATH", "mail.log".to_string()),
+            app_url: var_or("APP_URL", "http://localhost:4100".to_string()),
+            verification_ttl: var_or("EMAIL_VERIFICATION_TTL", 48 * 3600),
+        }
+    }
+}
+
+/// An OpenID Connect provider, configured through `OIDC_<NAME>_*` variables
+/// for every name listed in `OIDC_PROVIDERS`.
+pub struct OidcProvider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+fn oidc_providers_from_env() -> HashMap<String, OidcProvider> {
+    env::var("OIDC_PROVIDERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let prefix = format!("OIDC_{}_", name.to_uppercase());
+            let var = |key: &str| {
+                let var_name = format!("{}{}", prefix, key);
+                env::var(&var_name).unwrap_or_else(|_| panic!("{} must be set", var_name))
+            };
+            let provider = OidcProvider {
+                name: name.to_string(),
+                issuer: var("ISSUER"),
+                client_id: var("CLIENT_ID"),
+                client_secret: env::var(format!("{}CLIENT_SECRET", prefix)).ok(),
+                authorization_endpoint: var("AUTHORIZATION_ENDPOINT"),
+                token_endpoint: var("TOKEN_ENDPOINT"),
+                jwks_uri: var("JWKS_URI"),
+                redirect_uri: var("REDIRECT_URI"),
+                scopes: var_or(&format!("{}SCOPES", prefix), "openid email profile".to_string()),
+            };
+            (name.to_string(), provider)
+        })
+        .collect()
+}
+
+fn var_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -1,4 +1,5 @@
 use diesel::prelude::*;
+use diesel::sql_types::Text;
 use diesel::pg::PgConnection;
 use dotenv::dotenv;
 use std::env;
@@ -12,6 +13,12 @@ use rocket::{Outcome, Request, State};
 
 pub mod schema;
 
+// SQL `lower()`. Lookups by username or email go through it so they hit
+// the case-insensitive unique indexes on `users`.
+sql_function!(lower, lower_t, (a: Text) -> Text);
+// SQL `username_skeleton()`, see the `username_skeleton_index` migration.
+sql_function!(username_skeleton, username_skeleton_t, (a: Text) -> Text);
+
 // An alias to the type for a pool of Diesel Postgres connections.
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
@@ -9,6 +9,12 @@ table! {
         tag_list -> Array<Text>,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        language -> Text,
+        favorites_count -> Int4,
+        comments_count -> Int4,
+        hot_score -> Float8,
+        status -> Text,
+        published_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -39,13 +45,123 @@ table! {
     }
 }
 
+table! {
+    identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Text,
+        subject -> Text,
+        email -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    login_throttles (id) {
+        id -> Int4,
+        scope -> Text,
+        subject -> Text,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    oauth_states (id) {
+        id -> Int4,
+        state -> Text,
+        provider -> Text,
+        code_verifier -> Text,
+        nonce -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    password_resets (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    personal_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        scopes -> Array<Text>,
+        created_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        family -> Text,
+        token_hash -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+        replaced_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        family -> Text,
+        device_name -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    username_history (id) {
+        id -> Int4,
+        user_id -> Int4,
+        username -> Varchar,
+        changed_at -> Timestamptz,
+    }
+}
+
 table! {
     users (id) {
         id -> Int4,
         username -> Varchar,
-        token -> Text,
         email -> Text,
         bio -> Nullable<Text>,
         image -> Nullable<Text>,
+        password_hash -> Text,
+        email_verified_at -> Nullable<Timestamptz>,
+        tokens_valid_after -> Nullable<Timestamptz>,
+        totp_secret -> Nullable<Text>,
+        totp_enabled_at -> Nullable<Timestamptz>,
+        totp_last_step -> Nullable<Int8>,
+        role -> Text,
+        suspended_at -> Nullable<Timestamptz>,
+        suspended_reason -> Nullable<Text>,
     }
 }
@@ -0,0 +1,114 @@
+use config::{MailConfig, CONFIG};
+use lettre::smtp::authentication::Credentials;
+use lettre::{EmailTransport, SmtpTransport};
+use lettre_email::EmailBuilder;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use types::ApiError;
+
+#[derive(Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Outgoing mail. Managed as `Box<Mailer>` so tests and local setups can swap
+/// SMTP for a sink.
+pub trait Mailer: Send + Sync {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError>;
+}
+
+pub struct SmtpMailer {
+    from: String,
+    host: String,
+    credentials: Option<Credentials>,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError> {
+        let email = EmailBuilder::new()
+            .to(mail.to.as_str())
+            .from(self.from.as_str())
+            .subject(mail.subject.as_str())
+            .text(mail.body.as_str())
+            .build()
+            .map_err(|_| ApiError::Internal)?;
+
+        let mut builder = SmtpTransport::simple_builder(&self.host).map_err(|_| ApiError::Internal)?;
+        if let Some(ref credentials) = self.credentials {
+            builder = builder.credentials(credentials.clone());
+        }
+        builder
+            .build()
+            .send(&email)
+            .map(|_| ())
+            .map_err(|_| ApiError::Internal)
+    }
+}
+
+/// Appends every message to a file instead of delivering it.
+pub struct FileMailer {
+    path: String,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<String>>(path: P) -> FileMailer {
+        FileMailer {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError> {
+        let _guard = self.lock.lock().map_err(|_| ApiError::Internal)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        write!(
+            file,
+            "To: {}\nSubject: {}\n\n{}\n\n",
+            mail.to, mail.subject, mail.body
+        )?;
+        Ok(())
+    }
+}
+
+/// Drops every message and only logs who it was for. The body is left out
+/// because it carries sign-in and reset links.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, mail: &Mail) -> Result<(), ApiError> {
+        debug!("mail to {}: {}", mail.to, mail.subject);
+        Ok(())
+    }
+}
+
+pub fn from_config(config: &MailConfig) -> Box<Mailer> {
+    match config.transport.as_str() {
+        "smtp" => Box::new(SmtpMailer {
+            from: config.from.clone(),
+            host: config.smtp_host.clone(),
+            credentials: match (&config.smtp_username, &config.smtp_password) {
+                (&Some(ref username), &Some(ref password)) => {
+                    Some(Credentials::new(username.clone(), password.clone()))
+                }
+                _ => None,
+            },
+        }),
+        "file" => Box::new(FileMailer::new(config.sink_path.clone())),
+        "log" => Box::new(LogMailer),
+        other => panic!("Unknown MAIL_TRANSPORT: {}", other),
+    }
+}
+
+/// Absolute frontend link for `path`.
+pub fn app_link(path: &str) -> String {
+    format!("{}{}", CONFIG.mail.app_url.trim_right_matches('/'), path)
+}
@@ -21,12 +21,22 @@ extern crate diesel;
 extern crate r2d2;
 extern crate r2d2_diesel;
 
+extern crate argon2;
+extern crate base32;
+extern crate base64;
 extern crate chrono;
 extern crate crypto;
 extern crate jwt;
+extern crate lettre;
+extern crate lettre_email;
+#[macro_use]
+extern crate log;
+extern crate rand;
 #[macro_use]
 extern crate lazy_static;
 extern crate regex;
+extern crate reqwest;
+extern crate ring;
 #[macro_use]
 extern crate rocket_contrib;
 #[macro_use]
@@ -40,14 +50,22 @@ extern crate serde_json;
 //#extern crate validator_derive;
 
 extern crate slug;
+extern crate unicode_normalization;
+extern crate untrusted;
+extern crate zxcvbn;
 
+mod config;
 mod db;
+mod mailer;
 mod users;
 mod types;
 mod utils;
 mod profile;
 mod article;
 mod comment;
+mod admin;
+mod policy;
+mod pagination;
 
 use rocket::request::Request;
 use rocket::Error;
@@ -74,11 +92,45 @@ fn not_found(_req: &Request) -> content::Json<String> {
 }
 
 fn main() {
+    lazy_static::initialize(&config::CONFIG);
     let pool = db::init_pool().expect("Failed to create database pool");
     rocket::ignite()
         .manage(pool)
-        .mount("/api/users", routes!(users::register, users::login,))
-        .mount("/api", routes!(users::current, users::update))
+        .manage(mailer::from_config(&config::CONFIG.mail))
+        .mount(
+            "/api/users",
+            routes!(
+                users::register,
+                users::login,
+                users::login_second_factor,
+                users::oauth_authorize,
+                users::oauth_callback,
+                users::refresh,
+                users::logout,
+                users::verify,
+                users::resend_verification,
+                users::forgot_password,
+                users::reset_password
+            ),
+        )
+        .mount(
+            "/api",
+            routes!(
+                users::current,
+                users::update,
+                users::enroll_two_factor,
+                users::confirm_two_factor,
+                users::disable_two_factor,
+                users::create_token,
+                users::list_tokens,
+                users::revoke_token,
+                users::list_sessions,
+                users::revoke_session,
+                users::revoke_other_sessions,
+                users::delete,
+                users::export
+            ),
+        )
         .mount(
             "/api",
             routes!(profile::profile, profile::follow, profile::unfollow),
@@ -89,18 +141,41 @@ fn main() {
                 article::get,
                 article::create,
                 article::favorite,
+                article::unfavorite,
                 article::update,
                 article::delete,
                 article::list_without_params,
                 article::list,
                 article::feed,
                 article::feed_without_params,
+                article::search,
                 comment::add,
                 comment::get,
                 comment::delete
             ),
         )
-        .mount("/api/", routes!(article::tags))
+        .mount(
+            "/api/",
+            routes!(
+                article::tags,
+                article::drafts,
+                article::drafts_without_params
+            ),
+        )
+        .mount(
+            "/api/admin",
+            routes!(
+                admin::list_users,
+                admin::list_users_without_params,
+                admin::get_user,
+                admin::suspend_user,
+                admin::unsuspend_user,
+                admin::force_password_reset,
+                admin::change_role,
+                admin::delete_user,
+                admin::clear_lockout
+            ),
+        )
         .catch(errors![not_found, handle_422])
         .launch();
 }
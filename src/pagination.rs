@@ -0,0 +1,197 @@
+use base64;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use types::ValidationError;
+
+/// Most rows a single page may ask for.
+pub const MAX_LIMIT: i64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Rows after the cursor, in list order.
+    Next,
+    /// Rows before the cursor, in list order.
+    Prev,
+}
+
+/// Position in a sorted list, handed to clients as an opaque string so the
+/// key can change without breaking them.
+#[derive(Debug)]
+pub struct Cursor {
+    pub direction: Direction,
+    /// Name of the sort order the cursor belongs to.
+    pub order: String,
+    /// Sort key of the row the cursor points at, ties are broken by `id`.
+    pub key: String,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn new(direction: Direction, order: &str, key: String, id: i32) -> Cursor {
+        Cursor {
+            direction,
+            order: order.to_string(),
+            key,
+            id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            Direction::Next => "n",
+            Direction::Prev => "p",
+        };
+        let raw = format!("{}:{}:{}:{}", direction, self.order, self.key, self.id);
+        base64::encode_config(raw.as_bytes(), base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor, ValidationError> {
+        let raw = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| invalid_cursor())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid_cursor())?;
+
+        let parts = raw.split(':').collect::<Vec<_>>();
+        if parts.len() != 4 {
+            return Err(invalid_cursor());
+        }
+        let direction = match parts[0] {
+            "n" => Direction::Next,
+            "p" => Direction::Prev,
+            _ => return Err(invalid_cursor()),
+        };
+        let id = parts[3].parse::<i32>().map_err(|_| invalid_cursor())?;
+        Ok(Cursor::new(direction, parts[1], parts[2].to_string(), id))
+    }
+}
+
+pub fn invalid_cursor() -> ValidationError {
+    ValidationError::from("cursor", "invalid cursor")
+}
+
+/// Cursor key for a timestamp, in microseconds like Postgres stores it.
+pub fn time_key(time: DateTime<Utc>) -> String {
+    (time.timestamp() * 1_000_000 + time.timestamp_subsec_micros() as i64).to_string()
+}
+
+/// Reverses `time_key`. Keys outside what chrono can represent are reported
+/// as an invalid cursor.
+pub fn parse_time_key(key: &str) -> Result<DateTime<Utc>, ValidationError> {
+    let micros = match key.parse::<i64>() {
+        Ok(micros) if micros >= 0 => micros,
+        _ => return Err(invalid_cursor()),
+    };
+    NaiveDateTime::from_timestamp_opt(micros / 1_000_000, (micros % 1_000_000) as u32 * 1000)
+        .map(|time| DateTime::from_utc(time, Utc))
+        .ok_or_else(invalid_cursor)
+}
+
+/// Which slice of a list to return: `limit` rows either after `offset` rows
+/// or next to `cursor`.
+#[derive(Debug)]
+pub struct Page {
+    pub limit: i64,
+    pub offset: i64,
+    pub cursor: Option<Cursor>,
+}
+
+impl Page {
+    pub fn new(
+        limit: Option<i64>,
+        offset: Option<i64>,
+        cursor: Option<String>,
+    ) -> Result<Page, ValidationError> {
+        let mut errors = ValidationError::default();
+        let limit = limit.unwrap_or(20);
+        if limit < 1 || limit > MAX_LIMIT {
+            errors.add_error("limit", format!("limit must be between 1 and {}", MAX_LIMIT));
+        }
+        let offset_ = offset.unwrap_or(0);
+        if offset_ < 0 {
+            errors.add_error("offset", "offset must not be negative");
+        }
+
+        let cursor = match cursor {
+            Some(_) if offset.is_some() => {
+                errors.add_error("cursor", "cursor can not be combined with offset");
+                None
+            }
+            Some(ref cursor) => match Cursor::decode(cursor) {
+                Ok(cursor) => Some(cursor),
+                Err(error) => {
+                    errors.merge(error);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if errors.empty() {
+            Ok(Page {
+                limit,
+                offset: offset_,
+                cursor,
+            })
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Whether rows are fetched against list order, towards the start.
+    pub fn backwards(&self) -> bool {
+        self.cursor
+            .as_ref()
+            .map_or(false, |cursor| cursor.direction == Direction::Prev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::i64;
+
+    fn encode(raw: &[u8]) -> String {
+        base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let key = "1527854400123456".to_string();
+        let encoded = Cursor::new(Direction::Prev, "newest", key, 42).encode();
+        let cursor = Cursor::decode(&encoded).unwrap();
+        assert_eq!(cursor.direction, Direction::Prev);
+        assert_eq!(cursor.order, "newest");
+        assert_eq!(cursor.key, "1527854400123456");
+        assert_eq!(cursor.id, 42);
+
+        let encoded = Cursor::new(Direction::Next, "top", "7".to_string(), 1).encode();
+        let cursor = Cursor::decode(&encoded).unwrap();
+        assert_eq!(cursor.direction, Direction::Next);
+        assert_eq!(cursor.order, "top");
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        assert!(Cursor::decode("").is_err());
+        assert!(Cursor::decode("not a cursor!").is_err());
+        assert!(Cursor::decode(&encode(&[0xff, 0xfe])).is_err());
+        assert!(Cursor::decode(&encode(b"n:newest:1")).is_err());
+        assert!(Cursor::decode(&encode(b"n:newest:1:2:3")).is_err());
+        assert!(Cursor::decode(&encode(b"x:newest:1:2")).is_err());
+        assert!(Cursor::decode(&encode(b"n:newest:1:id")).is_err());
+    }
+
+    #[test]
+    fn out_of_range_cursors_are_rejected() {
+        assert!(Cursor::decode(&encode(b"n:newest:1:2147483648")).is_err());
+        assert!(parse_time_key("-1").is_err());
+        assert!(parse_time_key("soon").is_err());
+        assert!(parse_time_key(&i64::MAX.to_string()).is_err());
+        assert!(parse_time_key("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn time_keys_round_trip() {
+        let time = Utc.ymd(2018, 6, 1).and_hms_micro(12, 30, 0, 123_456);
+        assert_eq!(parse_time_key(&time_key(time)).unwrap(), time);
+    }
+}
@@ -0,0 +1,146 @@
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use std::io::Write;
+use types::ApiError;
+use users::models::User;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromSqlRow, AsExpression, Serialize, Deserialize)]
+#[sql_type = "Text"]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for Role {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for Role {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match not_none!(bytes) {
+            b"user" => Ok(Role::User),
+            b"moderator" => Ok(Role::Moderator),
+            b"admin" => Ok(Role::Admin),
+            _ => Err("Unrecognized role".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    CreateArticle,
+    UpdateArticle,
+    DeleteArticle,
+    CreateComment,
+    DeleteComment,
+    ManageUsers,
+}
+
+impl Permission {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Permission::CreateArticle => "articles:create",
+            Permission::UpdateArticle => "articles:update",
+            Permission::DeleteArticle => "articles:delete",
+            Permission::CreateComment => "comments:create",
+            Permission::DeleteComment => "comments:delete",
+            Permission::ManageUsers => "users:manage",
+        }
+    }
+}
+
+/// Whether `user` may do `permission` to something owned by `owner_id`.
+/// `owner_id` is `None` for actions that do not target an existing entity.
+pub fn can(user: &User, permission: Permission, owner_id: Option<i32>) -> bool {
+    let owns = owner_id == Some(user.id);
+    match (permission, user.role) {
+        (Permission::CreateArticle, _) | (Permission::CreateComment, _) => true,
+        (Permission::UpdateArticle, _) => owns,
+        (Permission::DeleteArticle, Role::User) | (Permission::DeleteComment, Role::User) => owns,
+        (Permission::DeleteArticle, _) | (Permission::DeleteComment, _) => true,
+        (Permission::ManageUsers, Role::Admin) => true,
+        (Permission::ManageUsers, _) => false,
+    }
+}
+
+/// Like `can`, but fails with `Forbidden` naming the missing permission.
+pub fn authorize(user: &User, permission: Permission, owner_id: Option<i32>) -> Result<(), ApiError> {
+    if can(user, permission, owner_id) {
+        Ok(())
+    } else {
+        Err(ApiError::Forbidden(permission))
+    }
+}
+
+/// What a personal access token may be used for. A route only accepts
+/// tokens if it declares a scope through `RouteScope`, everything else, the
+/// account and admin endpoints included, needs a real login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Read,
+    ArticlesWrite,
+    CommentsWrite,
+}
+
+impl Scope {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Scope::Read => "read",
+            Scope::ArticlesWrite => "articles:write",
+            Scope::CommentsWrite => "comments:write",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Scope> {
+        match name {
+            "read" => Some(Scope::Read),
+            "articles:write" => Some(Scope::ArticlesWrite),
+            "comments:write" => Some(Scope::CommentsWrite),
+            _ => None,
+        }
+    }
+}
+
+/// Declares the scope a personal access token needs on a route, by taking
+/// `users::ScopedUser<S>` instead of `CurrentUser` as its guard.
+pub trait RouteScope {
+    const SCOPE: Scope;
+}
+
+/// One `RouteScope` marker per scope.
+pub mod scope {
+    use super::{RouteScope, Scope};
+
+    pub struct Read;
+    pub struct ArticlesWrite;
+    pub struct CommentsWrite;
+
+    impl RouteScope for Read {
+        const SCOPE: Scope = Scope::Read;
+    }
+
+    impl RouteScope for ArticlesWrite {
+        const SCOPE: Scope = Scope::ArticlesWrite;
+    }
+
+    impl RouteScope for CommentsWrite {
+        const SCOPE: Scope = Scope::CommentsWrite;
+    }
+}
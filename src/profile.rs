@@ -1,4 +1,6 @@
 use users::models::User;
+use policy::scope;
+use users::{optional_user, ScopedUser};
 use types::*;
 use rocket_contrib::Json;
 use db::DbConnection;
@@ -28,7 +30,7 @@ pub struct Profile<'a> {
 #[get("/profiles/<name>", format = "application/json")]
 pub fn profile(
     connection: DbConnection,
-    current_user: Option<User>,
+    current_user: ScopedUser<scope::Read>,
     name: String,
 ) -> ApiResult<ProfileResponse<'static>> {
     use db::schema::users::dsl::*;
@@ -36,7 +38,7 @@ pub fn profile(
     use diesel::dsl::exists;
 
     let user = User::load_by_name(&name, &connection)?;
-    let following = match current_user {
+    let following = match optional_user(current_user)? {
         Some(current) => {
             let query = select(exists(
                 followers
@@ -4,7 +4,8 @@ use rocket::outcome::{IntoOutcome, Outcome};
 use rocket::request::Request;
 use serde::de::DeserializeOwned;
 use std::ops::Deref;
-use rocket::http::{ContentType, Status};
+use rocket::http::{ContentType, Method, Status};
+use rocket::http::uri::URI;
 use std::fmt::Debug;
 use rocket::response::{Responder, Response};
 use rocket::response::content;
@@ -17,6 +18,7 @@ use std::io::Error as IoError;
 use diesel::PgConnection;
 use utils::try_respond;
 use rocket::request::Outcome as RequestOutcome;
+use policy::{Permission, Scope};
 
 pub trait Validate
 where
@@ -31,8 +33,25 @@ pub enum ApiError {
     Diesel(DieselError),
     Validation(ValidationError),
     Internal,
+    /// The request carries no credentials.
     Unauthorized,
-    Forbidden,
+    /// The credentials are malformed or no longer valid. Carries the
+    /// RFC 6750 error code, `invalid_request` or `invalid_token`.
+    InvalidCredentials(&'static str),
+    /// The current user lacks the given permission.
+    Forbidden(Permission),
+    /// The personal access token used lacks the given scope, or `None` if
+    /// the route does not accept personal access tokens at all.
+    MissingScope(Option<Scope>),
+    /// The account was suspended by an admin, with an optional reason.
+    Suspended(Option<String>),
+    /// The current user has not verified their email address yet.
+    EmailNotVerified,
+    /// Too many failed attempts, retry after the given number of seconds.
+    TooManyRequests(i64),
+    /// The user named in the path has since been renamed from `from` to
+    /// `to`, the same request should be made against the new name.
+    Renamed { from: String, to: String },
 }
 
 impl From<DieselError> for ApiError {
@@ -86,6 +105,39 @@ impl ValidationError {
     }
 }
 
+/// The path and query of `req` with the segment naming `from` replaced by
+/// `to`.
+fn renamed_location(req: &Request, from: &str, to: &str) -> String {
+    let uri = req.uri();
+    let mut replaced = false;
+    let segments = uri.segments()
+        .map(|segment| {
+            let decoded = URI::percent_decode_lossy(segment.as_bytes());
+            if !replaced && decoded.to_lowercase() == from.to_lowercase() {
+                replaced = true;
+                URI::percent_encode(to).into_owned()
+            } else {
+                segment.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut location = format!("/{}", segments.join("/"));
+    if let Some(query) = uri.query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    location
+}
+
+/// `WWW-Authenticate` value offering both accepted schemes.
+fn authenticate_challenge(error: Option<&str>) -> String {
+    let params = match error {
+        Some(error) => format!("realm=\"api\", error=\"{}\"", error),
+        None => "realm=\"api\"".to_string(),
+    };
+    format!("Bearer {}, Token {}", params, params)
+}
+
 impl<'r> Responder<'r> for ApiError {
     fn respond_to(self, req: &Request) -> Result<Response<'r>, Status> {
         match self {
@@ -103,16 +155,90 @@ impl<'r> Responder<'r> for ApiError {
                 let body = json!({ "errors": {
                     "status": "401 Unauthorized"
                 }});
-                try_respond(req, &body, Status::raw(422))
+                try_respond(req, &body, Status::raw(401)).and_then(|resp| {
+                    Response::build_from(resp)
+                        .raw_header("WWW-Authenticate", authenticate_challenge(None))
+                        .ok()
+                })
+            }
+
+            ApiError::InvalidCredentials(error) => {
+                let body = json!({ "errors": {
+                    "status": "401 Unauthorized",
+                    "error": error
+                }});
+                try_respond(req, &body, Status::raw(401)).and_then(|resp| {
+                    Response::build_from(resp)
+                        .raw_header("WWW-Authenticate", authenticate_challenge(Some(error)))
+                        .ok()
+                })
+            }
+
+            ApiError::Forbidden(permission) => {
+                let body = json!({ "errors": {
+                    "status": "403 Forbidden",
+                    "permission": permission.name()
+                }});
+
+                try_respond(req, &body, Status::raw(403))
             }
 
-            ApiError::Forbidden => {
+            ApiError::MissingScope(scope) => {
                 let body = json!({ "errors": {
-                    "status": "403 Forbidden"
+                    "status": "403 Forbidden",
+                    "scope": scope.map(|scope| scope.name())
                 }});
 
                 try_respond(req, &body, Status::raw(403))
             }
+
+            ApiError::Suspended(reason) => {
+                let body = json!({ "errors": {
+                    "status": "403 Forbidden",
+                    "account": "suspended",
+                    "reason": reason
+                }});
+
+                try_respond(req, &body, Status::raw(403))
+            }
+
+            ApiError::EmailNotVerified => {
+                let body = json!({ "errors": {
+                    "email": ["email address is not verified"]
+                }});
+
+                try_respond(req, &body, Status::raw(403))
+            }
+
+            ApiError::TooManyRequests(retry_after) => {
+                let body = json!({ "errors": {
+                    "status": "429 Too Many Requests",
+                    "retryAfter": retry_after
+                }});
+
+                try_respond(req, &body, Status::raw(429)).and_then(|resp| {
+                    Response::build_from(resp)
+                        .raw_header("Retry-After", retry_after.to_string())
+                        .ok()
+                })
+            }
+
+            ApiError::Renamed { from, to } => {
+                let body = json!({ "errors": {
+                    "username": [format!("renamed to {}", to)]
+                }});
+                // 308 keeps the method and body, 301 only does so for reads.
+                let status = match req.method() {
+                    Method::Get | Method::Head => Status::raw(301),
+                    _ => Status::raw(308),
+                };
+
+                try_respond(req, &body, status).and_then(|resp| {
+                    Response::build_from(resp)
+                        .raw_header("Location", renamed_location(req, &from, &to))
+                        .ok()
+                })
+            }
             _ => Err(Status::raw(500)),
         }
     }
@@ -0,0 +1,199 @@
+use article::Article;
+use chrono::{DateTime, Utc};
+use db::schema::{articles, comments, favorites, followers, identities, password_resets,
+                 personal_tokens, recovery_codes, refresh_tokens, sessions, username_history,
+                 users};
+use diesel::prelude::*;
+use diesel::{delete as diesel_delete, update as diesel_update};
+use policy::Role;
+use std::net::IpAddr;
+use types::{ApiError, ValidationError};
+use utils::random_token;
+use super::models::User;
+use super::throttle::LoginThrottle;
+use super::two_factor;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedProfile {
+    username: String,
+    email: String,
+    bio: Option<String>,
+    image: Option<String>,
+    role: Role,
+    email_verified_at: Option<DateTime<Utc>>,
+    two_factor_enabled: bool,
+}
+
+#[derive(Debug, Serialize, Queryable)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedComment {
+    id: i32,
+    article_slug: String,
+    body: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+/// Everything stored about a user, as handed out by `GET /api/user/export`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Export {
+    exported_at: DateTime<Utc>,
+    profile: ExportedProfile,
+    articles: Vec<Article>,
+    comments: Vec<ExportedComment>,
+    /// Slugs of the articles the user favorited.
+    favorites: Vec<String>,
+    /// Usernames the user follows.
+    following: Vec<String>,
+    /// Usernames following the user.
+    followers: Vec<String>,
+    /// Usernames the user went by before, oldest first.
+    former_usernames: Vec<String>,
+}
+
+pub fn export(user: &User, conn: &PgConnection) -> Result<Export, ApiError> {
+    let articles = articles::table
+        .filter(articles::author_id.eq(user.id))
+        .order(articles::created_at.asc())
+        .load::<Article>(conn)?;
+
+    let comments = comments::table
+        .inner_join(articles::table.on(comments::article_id.eq(articles::id)))
+        .filter(comments::user_id.eq(user.id))
+        .select((
+            comments::id,
+            articles::slug,
+            comments::body,
+            comments::created_at,
+            comments::updated_at,
+        ))
+        .order(comments::created_at.asc())
+        .load::<ExportedComment>(conn)?;
+
+    let favorites = favorites::table
+        .inner_join(articles::table.on(favorites::article_id.eq(articles::id)))
+        .filter(favorites::user_id.eq(user.id))
+        .select(articles::slug)
+        .load::<String>(conn)?;
+
+    // In `followers`, `follower_id` follows `user_id`.
+    let following = followers::table
+        .inner_join(users::table.on(followers::user_id.eq(users::id)))
+        .filter(followers::follower_id.eq(user.id))
+        .select(users::username)
+        .load::<String>(conn)?;
+    let followers = followers::table
+        .inner_join(users::table.on(followers::follower_id.eq(users::id)))
+        .filter(followers::user_id.eq(user.id))
+        .select(users::username)
+        .load::<String>(conn)?;
+
+    let former_usernames = username_history::table
+        .filter(username_history::user_id.eq(user.id))
+        .order(username_history::changed_at.asc())
+        .select(username_history::username)
+        .load::<String>(conn)?;
+
+    Ok(Export {
+        exported_at: Utc::now(),
+        profile: ExportedProfile {
+            username: user.username.clone(),
+            email: user.email.clone(),
+            bio: user.bio.clone(),
+            image: user.image.clone(),
+            role: user.role,
+            email_verified_at: user.email_verified_at,
+            two_factor_enabled: user.has_two_factor(),
+        },
+        articles,
+        comments,
+        favorites,
+        following,
+        followers,
+        former_usernames,
+    })
+}
+
+/// Checks the password, and the second factor if enabled, before the
+/// account goes away. Users who only ever signed in through an identity
+/// provider have to set a password through the reset flow first. Failures
+/// count towards the login lockout like failed logins do.
+pub fn confirm(
+    user: &User,
+    password: &String,
+    code: Option<&str>,
+    ip: Option<IpAddr>,
+    conn: &PgConnection,
+) -> Result<(), ApiError> {
+    LoginThrottle::check(&user.email, ip, conn)?;
+    let confirmed = if !user.verify_password(password)? {
+        Err(ValidationError::from("password", "Invalid password").into())
+    } else if user.has_two_factor() {
+        two_factor::verify(user, code.unwrap_or(""), conn)
+    } else {
+        Ok(())
+    };
+    if let Err(e) = confirmed {
+        LoginThrottle::record_failure(&user.email, ip, conn)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Deletes `user` together with their articles and comments.
+pub fn delete(user: &User, conn: &PgConnection) -> Result<(), ApiError> {
+    conn.transaction::<_, ApiError, _>(|| {
+        LoginThrottle::clear_account(&user.email, conn)?;
+        diesel_delete(user).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Strips `user` of all personal data but keeps the row, so their articles
+/// and comments stay up under a `deleted-<id>` placeholder. Everything that
+/// could be used to sign in as them is dropped.
+pub fn anonymize(user: &User, conn: &PgConnection) -> Result<(), ApiError> {
+    let unusable_password = User::make_password(&random_token(32)?)?;
+    conn.transaction::<_, ApiError, _>(|| {
+        LoginThrottle::clear_account(&user.email, conn)?;
+        diesel_update(user)
+            .set((
+                users::username.eq(format!("deleted-{}", user.id)),
+                users::email.eq(format!("deleted-{}@invalid", user.id)),
+                users::bio.eq(None::<String>),
+                users::image.eq(None::<String>),
+                users::password_hash.eq(unusable_password),
+                users::email_verified_at.eq(None::<DateTime<Utc>>),
+                users::tokens_valid_after.eq(Utc::now()),
+                users::totp_secret.eq(None::<String>),
+                users::totp_enabled_at.eq(None::<DateTime<Utc>>),
+                users::totp_last_step.eq(None::<i64>),
+                users::role.eq(Role::User),
+            ))
+            .execute(conn)?;
+
+        diesel_delete(favorites::table.filter(favorites::user_id.eq(user.id))).execute(conn)?;
+        diesel_delete(
+            followers::table.filter(
+                followers::user_id
+                    .eq(user.id)
+                    .or(followers::follower_id.eq(user.id)),
+            ),
+        ).execute(conn)?;
+        diesel_delete(identities::table.filter(identities::user_id.eq(user.id))).execute(conn)?;
+        diesel_delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel_delete(password_resets::table.filter(password_resets::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel_delete(personal_tokens::table.filter(personal_tokens::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel_delete(sessions::table.filter(sessions::user_id.eq(user.id))).execute(conn)?;
+        diesel_delete(username_history::table.filter(username_history::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel_delete(refresh_tokens::table.filter(refresh_tokens::user_id.eq(user.id)))
+            .execute(conn)?;
+        Ok(())
+    })
+}
@@ -0,0 +1,56 @@
+use chrono::Utc;
+use config::CONFIG;
+use crypto::sha2::Sha256;
+use jwt::{Header, Registered, Token};
+use types::{ApiError, ValidationError};
+
+/// Audience of email verification tokens. Access tokens carry no audience.
+pub const VERIFY_EMAIL: &str = "verify-email";
+/// Audience of the token handed out after the password step of a 2FA login.
+pub const LOGIN_CHALLENGE: &str = "login-challenge";
+
+pub fn invalid_token() -> ApiError {
+    ApiError::Validation(ValidationError::from("token", "Invalid jwt token"))
+}
+
+/// Signs `claims` with the current server key, naming it in the `kid` header.
+pub fn sign(claims: Registered) -> Result<String, ApiError> {
+    let jwt = &CONFIG.jwt;
+    let header = Header {
+        kid: Some(jwt.current_kid.clone()),
+        ..Default::default()
+    };
+    Token::new(header, claims)
+        .signed(jwt.signing_key().as_bytes(), Sha256::new())
+        .map_err(|_| ApiError::Internal)
+}
+
+/// Parses `raw`, checks its signature against the key named by its `kid`
+/// header and rejects it once expired.
+pub fn verify(raw: &str) -> Result<Registered, ApiError> {
+    let jwt = &CONFIG.jwt;
+    let token = Token::<Header, Registered>::parse(raw).map_err(|_| invalid_token())?;
+
+    let verified = {
+        let kid = token
+            .header
+            .kid
+            .as_ref()
+            .map(|kid| kid.as_str())
+            .unwrap_or(jwt.current_kid.as_str());
+        match jwt.key(kid) {
+            Some(key) => token.verify(key.as_bytes(), Sha256::new()),
+            None => false,
+        }
+    };
+    if !verified {
+        return Err(invalid_token());
+    }
+
+    let now = Utc::now().timestamp() as u64;
+    match token.claims.exp {
+        Some(exp) if exp > now => Ok(token.claims),
+        Some(_) => Err(ValidationError::from("token", "Expired jwt token").into()),
+        None => Err(invalid_token()),
+    }
+}
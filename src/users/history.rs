@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use db::lower;
+use db::schema::{username_history, users};
+use diesel::insert_into;
+use diesel::prelude::*;
+use types::ApiError;
+use super::models::User;
+
+allow_tables_to_appear_in_same_query!(username_history, users);
+
+#[derive(Insertable)]
+#[table_name = "username_history"]
+struct NewUsernameChange<'a> {
+    user_id: i32,
+    username: &'a str,
+    changed_at: DateTime<Utc>,
+}
+
+/// Remembers that the user `user_id` no longer goes by `old_username`, so
+/// links to it keep working.
+pub fn record(user_id: i32, old_username: &str, conn: &PgConnection) -> Result<(), ApiError> {
+    let change = NewUsernameChange {
+        user_id,
+        username: old_username,
+        changed_at: Utc::now(),
+    };
+    insert_into(username_history::table)
+        .values(&change)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The user who most recently gave up `name`, under their current name.
+pub fn renamed_to(name: &str, conn: &PgConnection) -> Result<Option<User>, ApiError> {
+    username_history::table
+        .inner_join(users::table.on(username_history::user_id.eq(users::id)))
+        .filter(lower(username_history::username).eq(lower(name)))
+        .order(username_history::changed_at.desc())
+        .select(users::all_columns)
+        .first::<User>(conn)
+        .optional()
+        .map_err(|e| e.into())
+}
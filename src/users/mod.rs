@@ -1,8 +1,19 @@
 use rocket_contrib::Json;
 use rocket_contrib::Value;
 pub mod models;
+pub mod refresh;
+pub mod throttle;
+mod claims;
+mod verification;
+pub mod reset;
+mod two_factor;
+pub mod tokens;
+mod sessions;
+mod account;
+mod oidc;
+mod history;
 use db::schema::users;
-use db::{DbConnection, Pool};
+use db::{lower, DbConnection, Pool};
 use diesel::associations::HasTable;
 use diesel::dsl::exists;
 use diesel::insert_into;
@@ -19,6 +30,17 @@ use std::collections::HashMap;
 use std::convert::From;
 use std::ops::Deref;
 use types::{ApiError, ApiResult, Validate, ValidationError};
+use self::refresh::RefreshToken;
+use self::throttle::LoginThrottle;
+use self::reset::PasswordReset;
+use self::tokens::PersonalToken;
+use self::sessions::{CurrentSession, Device, Session};
+use diesel::result::Error as DieselError;
+use utils::ClientIp;
+use mailer::Mailer;
+use chrono::{DateTime, Utc};
+use policy::{RouteScope, Scope};
+use std::marker::PhantomData;
 
 mod utils;
 
@@ -42,8 +64,11 @@ impl Validate for Registration {
     fn validate(self, connection: &PgConnection) -> Result<Self, Self::Error> {
         use db::schema::users::dsl::*;
         let mut errors = ValidationError::default();
+        let mut registration = self;
+        registration.user.email = normalize_email(&registration.user.email);
+        registration.user.username = normalize_username(&registration.user.username);
 
-        let is_valid_email = validate_email(&self.user.email, connection);
+        let is_valid_email = validate_email(&registration.user.email, connection);
         match is_valid_email {
             Ok(_) => {}
             Err(e) => match e {
@@ -54,43 +79,56 @@ impl Validate for Registration {
             },
         }
 
-        let is_valid_password = validate_password(&self.user.password);
+        let is_valid_password = validate_password(
+            &registration.user.password,
+            &[
+                registration.user.username.as_str(),
+                registration.user.email.as_str(),
+            ],
+        );
         match is_valid_password {
             Ok(_) => {}
             Err(e) => errors.merge(e),
         }
 
-        let username_exists = select(exists(users.filter(username.eq(&self.user.username))))
-            .get_result::<bool>(connection)?;
-
-        if username_exists {
-            errors.add_error("username", "username allready exists");
+        match validate_username(&registration.user.username, None, connection) {
+            Ok(_) => {}
+            Err(ApiError::Validation(e)) => errors.merge(e),
+            Err(other) => return Err(other),
         }
 
         if errors.len() > 0 {
             Err(errors.into())
         } else {
-            Ok(self)
+            Ok(registration)
         }
     }
 }
 
 #[post("/", format = "application/json", data = "<registration>")]
-pub fn register(connection: DbConnection, registration: Json<Registration>) -> ApiResult<Value> {
+pub fn register(
+    connection: DbConnection,
+    device: Device,
+    mailer: State<Box<Mailer>>,
+    registration: Json<Registration>,
+) -> ApiResult<models::UserResponse> {
     use db::schema::users::dsl::*;
 
     let registration = registration.validate(&connection)?;
     let new_user = models::NewUser {
         username: registration.user.username.clone(),
         email: registration.user.email.clone(),
-        token: models::User::make_password(&registration.user.password)?,
+        password_hash: models::User::make_password(&registration.user.password)?,
     };
 
-    let mut user = insert_into(users)
-        .values(&new_user)
-        .get_result::<models::User>(&*connection)?;
-    user.token = user.token()?;
-    Ok(Json(json!({ "user": user })))
+    let user = connection.transaction::<_, ApiError, _>(|| {
+        let user = insert_into(users)
+            .values(&new_user)
+            .get_result::<models::User>(&*connection)?;
+        verification::send(&user, &**mailer)?;
+        Ok(user)
+    })?;
+    Ok(Json(sign_in(&user, &device, &connection)?))
 }
 
 #[derive(Debug, Deserialize)]
@@ -104,46 +142,177 @@ pub struct Login {
     user: LoginDetails,
 }
 
+/// Token of an `Authorization: Token <token>` or `Authorization: Bearer
+/// <token>` header value, `None` if the value is malformed.
+fn parse_authorization(header: &str) -> Option<&str> {
+    let mut parts = header.trim().splitn(2, ' ');
+    let scheme = parts.next()?;
+    let token = parts.next()?.trim();
+    let known_scheme = scheme.eq_ignore_ascii_case("Token") || scheme.eq_ignore_ascii_case("Bearer");
+    if known_scheme && !token.is_empty() && !token.contains(char::is_whitespace) {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+// Personal access tokens are only let through with `scope`, and never if
+// it is `None`.
+fn authenticate(
+    request: &Request,
+    scope: Option<Scope>,
+) -> request::Outcome<models::User, ApiError> {
+    let headers = request.headers();
+    let token_header = match headers.get_one("Authorization") {
+        Some(token_header) => token_header,
+        None => return Outcome::Failure((Status::Unauthorized, ApiError::Unauthorized)),
+    };
+    let token = match parse_authorization(token_header) {
+        Some(token) => token,
+        None => {
+            let error = ApiError::InvalidCredentials("invalid_request");
+            return Outcome::Failure((Status::Unauthorized, error));
+        }
+    };
+
+    let connection = match DbConnection::from_request(request) {
+        Outcome::Success(connection) => connection,
+        _ => return Outcome::Failure((Status::ServiceUnavailable, ApiError::Internal)),
+    };
+    let user = if token.starts_with(tokens::PREFIX) {
+        PersonalToken::authenticate(token, scope, &connection)
+    } else {
+        models::User::load_from_token(token, &connection)
+    };
+    match user {
+        Ok(user) => match user.require_active() {
+            Ok(_) => Outcome::Success(user),
+            Err(e) => Outcome::Failure((Status::Forbidden, e)),
+        },
+        Err(e) => match e {
+            ApiError::Validation(_) | ApiError::Diesel(DieselError::NotFound) => {
+                let error = ApiError::InvalidCredentials("invalid_token");
+                Outcome::Failure((Status::Unauthorized, error))
+            }
+            ApiError::MissingScope(_) => Outcome::Failure((Status::Forbidden, e)),
+            _ => Outcome::Failure((Status::ServiceUnavailable, ApiError::Internal)),
+        },
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for models::User {
     type Error = ApiError;
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
-        let headers = request.headers();
-        let token_header = headers.get_one("Authorization");
-        if let Some(token_header) = token_header {
-            let token = str::replace(token_header, "Token ", "");
-            let connection = DbConnection::from_request(request);
-            match connection {
-                Outcome::Success(connection) => {
-                    let user = models::User::load_from_token(&token, &connection);
-                    match user {
-                        Ok(user) => Outcome::Success(user),
-                        Err(e) => match e {
-                            ApiError::Validation(_) => Outcome::Failure((Status::raw(422), e)),
-                            _ => Outcome::Failure((Status::ServiceUnavailable, ApiError::Internal)),
-                        },
-                    }
-                }
-                _ => Outcome::Failure((Status::ServiceUnavailable, ApiError::Internal)),
-            }
-        } else {
-            Outcome::Failure((Status::raw(401), ApiError::Unauthorized))
-        }
+        authenticate(request, None)
     }
 }
 
+/// The caller of a route that personal access tokens with scope `S` may
+/// use. Routes taking `models::User` refuse those tokens.
+pub struct Scoped<S> {
+    pub user: models::User,
+    scope: PhantomData<S>,
+}
+
+/// `CurrentUser` for routes open to personal access tokens with scope `S`.
+pub type ScopedUser<S> = Result<Scoped<S>, ApiError>;
+
+impl<'a, 'r, S: RouteScope> FromRequest<'a, 'r> for Scoped<S> {
+    type Error = ApiError;
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        authenticate(request, Some(S::SCOPE)).map(|user| Scoped {
+            user,
+            scope: PhantomData,
+        })
+    }
+}
+
+impl<S> From<Scoped<S>> for models::User {
+    fn from(scoped: Scoped<S>) -> models::User {
+        scoped.user
+    }
+}
+
+/// Starts a session for `user` on `device` and builds the login response.
+fn sign_in(
+    user: &models::User,
+    device: &Device,
+    connection: &PgConnection,
+) -> Result<models::UserResponse, ApiError> {
+    let (session, refresh_token) = Session::start(user.id, device, connection)?;
+    user.response(session.id, Some(refresh_token))
+}
+
+/// Resolves the caller of a route that also serves anonymous requests.
+/// Missing or unusable credentials fall back to anonymous access rather
+/// than failing, any other error is passed on.
+pub fn optional_user<U: Into<models::User>>(
+    current_user: Result<U, ApiError>,
+) -> Result<Option<models::User>, ApiError> {
+    match current_user {
+        Ok(user) => Ok(Some(user.into())),
+        Err(ApiError::Unauthorized) | Err(ApiError::InvalidCredentials(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(models::UserResponse),
+    SecondFactorRequired(two_factor::Challenge),
+}
+
 #[post("/login", format = "application/json", data = "<login>")]
-pub fn login(connection: DbConnection, login: Json<Login>) -> ApiResult<Value> {
+pub fn login(
+    connection: DbConnection,
+    device: Device,
+    client_ip: ClientIp,
+    login: Json<Login>,
+) -> ApiResult<LoginResponse> {
     use db::schema::users::dsl::*;
-    let mut user = users
-        .filter(email.eq(&login.user.email))
-        .first::<models::User>(&*connection)?;
+    let login_email = normalize_email(&login.user.email);
+    LoginThrottle::check(&login_email, client_ip.0, &connection)?;
+
+    let user = users
+        .filter(lower(email).eq(&login_email))
+        .first::<models::User>(&*connection)
+        .optional()?;
+    let mut user = match user {
+        Some(user) => user,
+        None => {
+            LoginThrottle::record_failure(&login_email, client_ip.0, &connection)?;
+            return Err(DieselError::NotFound.into());
+        }
+    };
+
     let password_is_valid = user.verify_password(&login.user.password)?;
     match password_is_valid {
         true => {
-            user.token = user.token()?;
-            Ok(Json(json!({ "user": user })))
+            user.require_active()?;
+            if user.password_needs_rehash() {
+                user.new_password(&login.user.password)?;
+                diesel_update(&user)
+                    .set(password_hash.eq(&user.password_hash))
+                    .execute(&*connection)?;
+            }
+
+            // The lockout stays until the second factor is in too, otherwise
+            // every correct password would reset the budget for guessing codes.
+            if user.has_two_factor() {
+                let challenge = two_factor::challenge(&user)?;
+                return Ok(Json(LoginResponse::SecondFactorRequired(challenge)));
+            }
+
+            LoginThrottle::succeeded(&login_email, client_ip.0, &connection)?;
+            Ok(Json(LoginResponse::Authenticated(sign_in(
+                &user,
+                &device,
+                &connection,
+            )?)))
         }
         false => {
+            LoginThrottle::record_failure(&login_email, client_ip.0, &connection)?;
             let mut error = ValidationError::default();
             error.add_error("password", "Invalid password");
             Err(error.into())
@@ -151,20 +320,345 @@ pub fn login(connection: DbConnection, login: Json<Login>) -> ApiResult<Value> {
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct SecondFactorLogin {
+    #[serde(rename = "challengeToken")]
+    challenge_token: String,
+    code: String,
+}
+
+#[post("/login/2fa", format = "application/json", data = "<login>")]
+pub fn login_second_factor(
+    connection: DbConnection,
+    device: Device,
+    client_ip: ClientIp,
+    login: Json<SecondFactorLogin>,
+) -> ApiResult<models::UserResponse> {
+    let user = models::User::load_from_claims(
+        &login.challenge_token,
+        Some(claims::LOGIN_CHALLENGE),
+        &connection,
+    )?;
+    user.require_active()?;
+    LoginThrottle::check(&user.email, client_ip.0, &connection)?;
+    if let Err(e) = two_factor::verify(&user, &login.code, &connection) {
+        LoginThrottle::record_failure(&user.email, client_ip.0, &connection)?;
+        return Err(e);
+    }
+
+    LoginThrottle::succeeded(&user.email, client_ip.0, &connection)?;
+    Ok(Json(sign_in(&user, &device, &connection)?))
+}
+
+#[get("/oauth/<provider>/authorize", format = "application/json")]
+pub fn oauth_authorize(
+    connection: DbConnection,
+    provider: String,
+) -> ApiResult<oidc::Authorization> {
+    let provider = oidc::provider(&provider)?;
+    Ok(Json(oidc::authorize(provider, &connection)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OAuthCallback {
+    code: String,
+    state: String,
+}
+
+#[post("/oauth/<provider>/callback", format = "application/json", data = "<callback>")]
+pub fn oauth_callback(
+    connection: DbConnection,
+    device: Device,
+    provider: String,
+    callback: Json<OAuthCallback>,
+) -> ApiResult<LoginResponse> {
+    let provider = oidc::provider(&provider)?;
+    let user = oidc::callback(provider, &callback.code, &callback.state, &connection)?;
+    user.require_active()?;
+    if user.has_two_factor() {
+        let challenge = two_factor::challenge(&user)?;
+        return Ok(Json(LoginResponse::SecondFactorRequired(challenge)));
+    }
+
+    Ok(Json(LoginResponse::Authenticated(sign_in(
+        &user,
+        &device,
+        &connection,
+    )?)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    #[serde(rename = "refreshToken")]
+    refresh_token: String,
+}
+
+#[post("/refresh", format = "application/json", data = "<refresh>")]
+pub fn refresh(
+    connection: DbConnection,
+    device: Device,
+    refresh: Json<RefreshRequest>,
+) -> ApiResult<models::UserResponse> {
+    let (next, refresh_token) = RefreshToken::rotate(&refresh.refresh_token, &connection)?;
+    let user = models::User::load_by_id(&next.user_id, &connection)?;
+    user.require_active()?;
+    let session = Session::refreshed(&next, &device, &connection)?;
+    Ok(Json(user.response(session.id, Some(refresh_token))?))
+}
+
+#[post("/logout", format = "application/json", data = "<logout>")]
+pub fn logout(connection: DbConnection, logout: Json<RefreshRequest>) -> ApiResult<()> {
+    RefreshToken::revoke(&logout.refresh_token, &connection)?;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    token: String,
+}
+
+/// Confirms the email address only. The token travels by mail, so it must not
+/// double as a way to sign in.
+#[post("/verify", format = "application/json", data = "<verify>")]
+pub fn verify(connection: DbConnection, verify: Json<VerifyRequest>) -> ApiResult<()> {
+    verification::confirm(&verify.token, &connection)?;
+    Ok(Json(()))
+}
+
+#[post("/verify/resend", format = "application/json")]
+pub fn resend_verification(
+    connection: DbConnection,
+    current_user: CurrentUser,
+    client_ip: ClientIp,
+    mailer: State<Box<Mailer>>,
+) -> ApiResult<()> {
+    let user = current_user?;
+    if user.is_verified() {
+        return Err(ValidationError::from("email", "Email already verified").into());
+    }
+
+    LoginThrottle::record_mail(&user.email, client_ip.0, &connection)?;
+    verification::send(&user, &**mailer)?;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[post("/password/forgot", format = "application/json", data = "<forgot>")]
+pub fn forgot_password(
+    connection: DbConnection,
+    client_ip: ClientIp,
+    mailer: State<Box<Mailer>>,
+    forgot: Json<ForgotPassword>,
+) -> ApiResult<()> {
+    // Counted before the lookup, so unknown addresses are refused just the same.
+    let email = normalize_email(&forgot.email);
+    LoginThrottle::record_mail(&email, client_ip.0, &connection)?;
+    PasswordReset::request(&email, &connection, &**mailer)?;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPassword {
+    token: String,
+    password: String,
+}
+
+#[post("/password/reset", format = "application/json", data = "<reset>")]
+pub fn reset_password(connection: DbConnection, reset: Json<ResetPassword>) -> ApiResult<()> {
+    PasswordReset::reset(&reset.token, &reset.password, &connection)?;
+    Ok(Json(()))
+}
+
 #[get("/user", format = "application/json")]
-pub fn current(user: Result<models::User, ApiError>) -> ApiResult<Value> {
-    let mut user = user?;
-    user.token = user.token()?;
-    let user = json!({ "user": user });
-    Ok(Json(user))
+pub fn current(
+    user: Result<models::User, ApiError>,
+    session: CurrentSession,
+) -> ApiResult<models::UserResponse> {
+    let user = user?;
+    let access_token = session.0.ok_or(ApiError::Unauthorized)?;
+    Ok(Json(user.response_with(access_token.token, None)))
+}
+
+#[post("/user/2fa", format = "application/json")]
+pub fn enroll_two_factor(
+    current_user: CurrentUser,
+    connection: DbConnection,
+) -> ApiResult<two_factor::Enrollment> {
+    let user = current_user?;
+    Ok(Json(two_factor::enroll(&user, &connection)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCode {
+    code: String,
+}
+
+#[post("/user/2fa/confirm", format = "application/json", data = "<confirm>")]
+pub fn confirm_two_factor(
+    current_user: CurrentUser,
+    connection: DbConnection,
+    confirm: Json<TwoFactorCode>,
+) -> ApiResult<two_factor::RecoveryCodes> {
+    let user = current_user?;
+    Ok(Json(two_factor::confirm(&user, &confirm.code, &connection)?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactor {
+    password: String,
+    code: String,
+}
+
+#[delete("/user/2fa", format = "application/json", data = "<disable>")]
+pub fn disable_two_factor(
+    current_user: CurrentUser,
+    connection: DbConnection,
+    client_ip: ClientIp,
+    disable: Json<DisableTwoFactor>,
+) -> ApiResult<()> {
+    let user = current_user?;
+    two_factor::disable(
+        &user,
+        &disable.password,
+        &disable.code,
+        client_ip.0,
+        &connection,
+    )?;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewTokenDetails {
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewToken {
+    token: NewTokenDetails,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenList {
+    tokens: Vec<PersonalToken>,
+}
+
+#[post("/user/tokens", format = "application/json", data = "<new_token>")]
+pub fn create_token(
+    current_user: CurrentUser,
+    connection: DbConnection,
+    new_token: Json<NewToken>,
+) -> ApiResult<tokens::CreatedToken> {
+    let user = current_user?;
+    let details = &new_token.token;
+    let created = PersonalToken::create(
+        user.id,
+        &details.name,
+        &details.scopes,
+        details.expires_at,
+        &connection,
+    )?;
+    Ok(Json(created))
+}
+
+#[get("/user/tokens", format = "application/json")]
+pub fn list_tokens(current_user: CurrentUser, connection: DbConnection) -> ApiResult<TokenList> {
+    let user = current_user?;
+    Ok(Json(TokenList {
+        tokens: PersonalToken::list(user.id, &connection)?,
+    }))
+}
+
+#[delete("/user/tokens/<id>", format = "application/json")]
+pub fn revoke_token(current_user: CurrentUser, connection: DbConnection, id: i32) -> ApiResult<()> {
+    let user = current_user?;
+    PersonalToken::revoke(user.id, id, &connection)?;
+    Ok(Json(()))
+}
+
+#[get("/user/sessions", format = "application/json")]
+pub fn list_sessions(
+    current_user: CurrentUser,
+    session: CurrentSession,
+    connection: DbConnection,
+) -> ApiResult<sessions::SessionList> {
+    let user = current_user?;
+    Ok(Json(Session::list(user.id, session.id(), &connection)?))
+}
+
+#[delete("/user/sessions/<id>", format = "application/json")]
+pub fn revoke_session(current_user: CurrentUser, connection: DbConnection, id: i32) -> ApiResult<()> {
+    let user = current_user?;
+    Session::revoke(user.id, id, &connection)?;
+    Ok(Json(()))
+}
+
+/// Signs out every other device, keeping the session making the request.
+#[delete("/user/sessions", format = "application/json")]
+pub fn revoke_other_sessions(
+    current_user: CurrentUser,
+    session: CurrentSession,
+    connection: DbConnection,
+) -> ApiResult<()> {
+    let user = current_user?;
+    let session_id = session.id().ok_or(ApiError::Unauthorized)?;
+    Session::revoke_others(user.id, session_id, &connection)?;
+    Ok(Json(()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DeletionDetails {
+    password: String,
+    /// Required when two-factor authentication is enabled.
+    code: Option<String>,
+    /// Keep articles and comments up under a placeholder instead of
+    /// deleting them along with the account.
+    #[serde(default)]
+    keep_articles: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Deletion {
+    user: DeletionDetails,
 }
 
-#[derive(Debug, Deserialize, AsChangeset)]
-#[table_name = "users"]
+#[delete("/user", format = "application/json", data = "<deletion>")]
+pub fn delete(
+    current_user: CurrentUser,
+    connection: DbConnection,
+    client_ip: ClientIp,
+    deletion: Json<Deletion>,
+) -> ApiResult<()> {
+    let user = current_user?;
+    let details = &deletion.user;
+    let code = details.code.as_ref().map(|code| code.as_str());
+    account::confirm(&user, &details.password, code, client_ip.0, &connection)?;
+
+    if details.keep_articles {
+        account::anonymize(&user, &connection)?;
+    } else {
+        account::delete(&user, &connection)?;
+    }
+    Ok(Json(()))
+}
+
+#[get("/user/export", format = "application/json")]
+pub fn export(current_user: CurrentUser, connection: DbConnection) -> ApiResult<account::Export> {
+    let user = current_user?;
+    Ok(Json(account::export(&user, &connection)?))
+}
+
+#[derive(Debug, Deserialize)]
 pub struct UpdateUser {
     pub username: Option<String>,
     pub email: Option<String>,
-    #[column_name = "token"]
     pub password: Option<String>,
     pub image: Option<String>,
     pub bio: Option<String>,
@@ -178,19 +672,29 @@ pub struct Update {
 #[put("/user", format = "application/json", data = "<update>")]
 pub fn update(
     curent_user: CurrentUser,
+    session: CurrentSession,
     connection: DbConnection,
+    client_ip: ClientIp,
+    mailer: State<Box<Mailer>>,
     update: Json<Update>,
-) -> ApiResult<Value> {
+) -> ApiResult<models::UserResponse> {
     use db::schema::users::dsl::*;
 
     let mut user = curent_user?;
+    // Resolved up front: failing after the commit below would report an
+    // error for an update that went through.
+    let access_token = session.0.ok_or(ApiError::Unauthorized)?;
     let mut error = ValidationError::default();
     let update = update.into_inner();
 
     user.bio = update.user.bio;
     user.image = update.user.image;
+    let previous_email = user.email.clone();
+    let previous_username = user.username.clone();
+    let mut password_changed = false;
 
     if let Some(new_email) = update.user.email {
+        let new_email = normalize_email(&new_email);
         let is_valid = validate_email_re(&new_email);
         match is_valid {
             Err(e) => {
@@ -201,7 +705,9 @@ pub fn update(
             }
         }
 
-        let expr = users.filter(email.eq(&user.email)).filter(id.ne(&user.id));
+        let expr = users
+            .filter(lower(email).eq(&user.email))
+            .filter(id.ne(&user.id));
         let email_exists = select(exists(expr)).get_result::<bool>(&*connection)?;
         if email_exists {
             error.add_error("email", format!("Email already chosen: {}", &user.email));
@@ -209,35 +715,30 @@ pub fn update(
     }
 
     if let Some(new_username) = update.user.username {
-        let is_valid = validate_username_re(&new_username);
-        match is_valid {
-            Err(e) => {
-                error.merge(e);
-            }
-            Ok(_) => {
-                user.username = new_username;
+        // Resending the current name must keep working even if it predates
+        // a stricter policy.
+        let new_username = normalize_username(&new_username);
+        if new_username != user.username {
+            match validate_username(&new_username, Some(user.id), &connection) {
+                Ok(_) => user.username = new_username,
+                Err(ApiError::Validation(e)) => error.merge(e),
+                Err(other) => return Err(other),
             }
         }
-        let expr = users
-            .filter(username.eq(&user.username))
-            .filter(id.ne(user.id));
-        let username_exists = select(exists(expr)).get_result::<bool>(&*connection)?;
-        if username_exists {
-            error.add_error(
-                "username",
-                format!("Username already chosen: {}", user.username),
-            );
-        }
     }
 
     if let Some(new_password) = update.user.password {
-        let is_valid = validate_password(&new_password);
+        let is_valid = validate_password(
+            &new_password,
+            &[user.username.as_str(), user.email.as_str()],
+        );
         match is_valid {
             Err(e) => {
                 error.merge(e);
             }
             _ => {
                 user.new_password(&new_password)?;
+                password_changed = true;
             }
         }
     }
@@ -245,8 +746,43 @@ pub fn update(
     if !error.empty() {
         Err(error.into())
     } else {
-        diesel_update(&user).set(&user).execute(&*connection)?;
-        user.token = user.token()?;
-        Ok(Json(json!({ "user": user })))
+        connection.transaction::<_, ApiError, _>(|| {
+            // Only the columns this route edits: writing the whole row back
+            // would undo a suspension or role change made in the meantime.
+            let new_password_hash = if password_changed {
+                Some(password_hash.eq(&user.password_hash))
+            } else {
+                None
+            };
+            diesel_update(&user)
+                .set((
+                    username.eq(&user.username),
+                    email.eq(&user.email),
+                    bio.eq(&user.bio),
+                    image.eq(&user.image),
+                    new_password_hash,
+                ))
+                .execute(&*connection)?;
+            if user.username.to_lowercase() != previous_username.to_lowercase() {
+                history::record(user.id, &previous_username, &connection)?;
+            }
+            if user.email != previous_email {
+                user.email_verified_at = None;
+                diesel_update(&user)
+                    .set(email_verified_at.eq(None::<DateTime<Utc>>))
+                    .execute(&*connection)?;
+                LoginThrottle::record_mail(&user.email, client_ip.0, &connection)?;
+                verification::send(&user, &**mailer)?;
+            }
+            Ok(())
+        })?;
+        // Access tokens are bound to the email, so a changed one needs a
+        // new token; anything else keeps the one the client holds.
+        let token = if user.email != previous_email {
+            user.reissue_token(&access_token)?
+        } else {
+            access_token.token
+        };
+        Ok(Json(user.response_with(token, None)))
     }
 }
@@ -1,91 +1,359 @@
 use diesel::prelude::*;
-use db::{DbConnection, TryLoadById};
+use db::{lower, DbConnection, TryLoadById};
 use db::schema::{followers, users};
 use crypto::pbkdf2::*;
 use crypto::sha2::Sha256;
-use std::io::Result as IoResult;
 use types::{ApiError, ValidationError};
-use jwt::{Header, Registered, Token};
+use jwt::Registered;
 use profile::Profile;
+use policy::Role;
 use std::borrow::Cow;
 use diesel::select;
 use diesel::dsl::exists;
+use diesel::result::Error as DieselError;
+use chrono::{DateTime, Utc};
+use super::claims;
+use super::history;
+use super::sessions::{AccessToken, Session};
+use config::CONFIG;
+use utils::{random_bytes, random_token};
+use argon2;
 
-#[derive(Debug, Queryable, Identifiable, Serialize, AsChangeset)]
+// Deliberately not `Serialize`: it carries the password hash. Responses go
+// through `UserResponse` instead. Not `AsChangeset` either, so saving a
+// profile can never write back role, suspension or credentials by accident.
+#[derive(Debug, Queryable, Identifiable)]
 pub struct User {
-    #[serde(skip_serializing)]
     pub id: i32,
     pub username: String,
-    pub token: String,
     pub email: String,
     pub bio: Option<String>,
     pub image: Option<String>,
+    pub password_hash: String,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    /// Tokens issued at or before this moment are rejected.
+    pub tokens_valid_after: Option<DateTime<Utc>>,
+    /// Base32 TOTP secret, pending until `totp_enabled_at` is set.
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    /// Last accepted TOTP time step, so a code cannot be replayed.
+    pub totp_last_step: Option<i64>,
+    pub role: Role,
+    pub suspended_at: Option<DateTime<Utc>>,
+    pub suspended_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthUser {
+    pub email: String,
+    pub token: String,
+    pub username: String,
+    pub bio: Option<String>,
+    pub image: Option<String>,
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserResponse {
+    pub user: AuthUser,
+    #[serde(rename = "refreshToken", skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+pub trait PasswordHasher {
+    /// Whether `hash` was produced by this hasher.
+    fn handles(&self, hash: &str) -> bool;
+    fn hash(&self, password: &str) -> Result<String, ApiError>;
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, ApiError>;
+    /// Whether `hash` should be replaced by one made with the current settings.
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+pub struct Argon2idHasher {
+    pub mem_cost: u32,
+    pub time_cost: u32,
+    pub lanes: u32,
+}
+
+impl Argon2idHasher {
+    fn config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            ..argon2::Config::default()
+        }
+    }
+
+    fn params_prefix(&self) -> String {
+        format!(
+            "$argon2id$v=19$m={},t={},p={}$",
+            self.mem_cost, self.time_cost, self.lanes
+        )
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn handles(&self, hash: &str) -> bool {
+        hash.starts_with("$argon2id$")
+    }
+
+    fn hash(&self, password: &str) -> Result<String, ApiError> {
+        let salt = random_bytes(16)?;
+        argon2::hash_encoded(password.as_bytes(), &salt, &self.config())
+            .map_err(|_| ApiError::Internal)
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, ApiError> {
+        argon2::verify_encoded(hash, password.as_bytes()).map_err(|_| ApiError::Internal)
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        !hash.starts_with(&self.params_prefix())
+    }
+}
+
+/// Hashes written by `pbkdf2_simple` before Argon2id was introduced. Only
+/// used to verify them until their owners log in again.
+pub struct Pbkdf2Hasher {
+    pub iterations: u32,
+}
+
+impl PasswordHasher for Pbkdf2Hasher {
+    fn handles(&self, hash: &str) -> bool {
+        hash.starts_with("$rpbkdf2$")
+    }
+
+    fn hash(&self, password: &str) -> Result<String, ApiError> {
+        pbkdf2_simple(password, self.iterations).map_err(|e| e.into())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, ApiError> {
+        pbkdf2_check(password, hash).map_err(|_| ApiError::Internal)
+    }
+
+    fn needs_rehash(&self, _hash: &str) -> bool {
+        true
+    }
+}
+
+lazy_static! {
+    static ref ARGON2ID: Argon2idHasher = Argon2idHasher {
+        mem_cost: CONFIG.password.argon2_mem_cost,
+        time_cost: CONFIG.password.argon2_time_cost,
+        lanes: CONFIG.password.argon2_lanes,
+    };
+}
+
+static PBKDF2: Pbkdf2Hasher = Pbkdf2Hasher { iterations: 1000 };
+
+/// The hasher every new password goes through.
+pub fn current_hasher() -> &'static PasswordHasher {
+    &*ARGON2ID
+}
+
+fn hasher_for(hash: &str) -> Option<&'static PasswordHasher> {
+    let hashers: [&'static PasswordHasher; 2] = [&*ARGON2ID, &PBKDF2];
+    hashers.iter().cloned().find(|hasher| hasher.handles(hash))
 }
 
 impl User {
-    pub fn make_password(password: &String) -> IoResult<String> {
-        pbkdf2_simple(password, 1000)
+    pub fn make_password(password: &String) -> Result<String, ApiError> {
+        current_hasher().hash(password)
     }
 
-    pub fn new_password(&mut self, password: &String) -> IoResult<()> {
-        self.token = pbkdf2_simple(password, 1000)?;
+    pub fn new_password(&mut self, password: &String) -> Result<(), ApiError> {
+        self.password_hash = current_hasher().hash(password)?;
         Ok(())
     }
 
     pub fn verify_password(&self, password_to_verify: &String) -> Result<bool, ApiError> {
-        let check = pbkdf2_check(password_to_verify, &self.token);
-        check.map_err(|_| ApiError::Internal)
+        match hasher_for(&self.password_hash) {
+            Some(hasher) => hasher.verify(password_to_verify, &self.password_hash),
+            None => Err(ApiError::Internal),
+        }
+    }
+
+    pub fn password_needs_rehash(&self) -> bool {
+        let current = current_hasher();
+        !current.handles(&self.password_hash) || current.needs_rehash(&self.password_hash)
+    }
+
+    /// Builds the `{"user": {...}}` body with a freshly signed access token
+    /// for session `session_id`.
+    pub fn response(
+        &self,
+        session_id: i32,
+        refresh_token: Option<String>,
+    ) -> Result<UserResponse, ApiError> {
+        Ok(self.response_with(self.token(session_id)?, refresh_token))
     }
 
-    pub fn token(&self) -> Result<String, ApiError> {
-        let header = Header::default();
+    /// Builds the `{"user": {...}}` body around an access token the caller
+    /// already has.
+    pub fn response_with(&self, token: String, refresh_token: Option<String>) -> UserResponse {
+        UserResponse {
+            user: AuthUser {
+                email: self.email.clone(),
+                token,
+                username: self.username.clone(),
+                bio: self.bio.clone(),
+                image: self.image.clone(),
+                role: self.role,
+            },
+            refresh_token,
+        }
+    }
+
+    // Tokens name the user by id and bind to their current email.
+    fn signed_token(
+        &self,
+        audience: Option<&str>,
+        ttl: u64,
+        jti: String,
+    ) -> Result<String, ApiError> {
+        let expires_at = Utc::now().timestamp() as u64 + ttl;
+        self.signed_token_until(audience, expires_at, jti)
+    }
+
+    fn signed_token_until(
+        &self,
+        audience: Option<&str>,
+        expires_at: u64,
+        jti: String,
+    ) -> Result<String, ApiError> {
+        let now = Utc::now().timestamp() as u64;
         let claims = Registered {
             iss: Some(self.email.clone()),
             sub: Some(self.id.to_string()),
+            aud: audience.map(|aud| aud.to_string()),
+            iat: Some(now),
+            exp: Some(expires_at),
+            jti: Some(jti),
             ..Default::default()
         };
-        let token = Token::new(header, claims);
-        token
-            .signed(self.token.as_bytes(), Sha256::new())
-            .map_err(|_| ApiError::Internal)
+        claims::sign(claims)
     }
 
-    pub fn verify_token(&self, token: &Token<Header, Registered>) -> bool {
-        token.verify(&self.token.as_bytes(), Sha256::new())
+    /// Access token of session `session_id`, which it names as its `jti`.
+    pub fn token(&self, session_id: i32) -> Result<String, ApiError> {
+        self.signed_token(None, CONFIG.jwt.ttl, session_id.to_string())
     }
 
-    pub fn load_from_token(jwt_token: &str, connection: &PgConnection) -> Result<User, ApiError> {
+    /// Stands in for `access_token` once the email it is bound to changed.
+    /// Runs out when `access_token` would have, so that editing the profile
+    /// does not extend a session.
+    pub fn reissue_token(&self, access_token: &AccessToken) -> Result<String, ApiError> {
+        self.signed_token_until(
+            None,
+            access_token.expires_at,
+            access_token.session_id.to_string(),
+        )
+    }
+
+    /// Token mailed to the user to prove they own `self.email`.
+    pub fn verification_token(&self) -> Result<String, ApiError> {
+        let jti = random_token(16)?;
+        self.signed_token(Some(claims::VERIFY_EMAIL), CONFIG.mail.verification_ttl, jti)
+    }
+
+    /// Short lived token that stands in for the password while the user
+    /// provides their second factor.
+    pub fn login_challenge_token(&self) -> Result<String, ApiError> {
+        self.signed_token(Some(claims::LOGIN_CHALLENGE), 300, random_token(16)?)
+    }
+
+    /// Loads the user a signed token with audience `audience` was issued to.
+    /// The token stops matching once the user changes their email.
+    pub fn load_from_claims(
+        raw: &str,
+        audience: Option<&str>,
+        connection: &PgConnection,
+    ) -> Result<User, ApiError> {
+        let claims = claims::verify(raw)?;
+        User::load_verified(&claims, audience, false, connection)
+    }
+
+    /// `session_bound` tokens have their session checked by the caller.
+    fn load_verified(
+        claims: &Registered,
+        audience: Option<&str>,
+        session_bound: bool,
+        connection: &PgConnection,
+    ) -> Result<User, ApiError> {
         use db::schema::users::dsl::*;
-        let jwt_token = Token::<Header, Registered>::parse(jwt_token);
-        let mut e = ValidationError::default();
-        match jwt_token {
-            Ok(jwt_token) => {
-                let sub = &jwt_token.claims.sub;
-                match sub {
-                    &None => Err(ValidationError::from("token", "Invalid jwt token").into()),
-                    &Some(ref user_id) => match &jwt_token.claims.iss {
-                        &None => Err(ValidationError::from("token", "Invalid jwt token").into()),
-                        &Some(ref user_email) => {
-                            let user_id = user_id.parse::<i32>().map_err(|_| {
-                                ApiError::Validation(ValidationError::from(
-                                    "token",
-                                    "Invalid jwt token",
-                                ))
-                            })?;
-
-                            let user = users
-                                .filter(id.eq(user_id))
-                                .filter(email.eq(user_email))
-                                .get_result::<User>(connection)?;
-                            Ok(user)
-                        }
-                    },
+        if claims.aud.as_ref().map(|aud| aud.as_str()) != audience {
+            return Err(claims::invalid_token());
+        }
+
+        match (&claims.sub, &claims.iss) {
+            (&Some(ref user_id), &Some(ref user_email)) => {
+                let user_id = user_id
+                    .parse::<i32>()
+                    .map_err(|_| claims::invalid_token())?;
+                let user = users
+                    .filter(id.eq(user_id))
+                    .filter(email.eq(user_email))
+                    .get_result::<User>(connection)?;
+                // `iat` only has whole seconds. A token from the second of the
+                // cut-off can only be trusted if it belongs to a session, as
+                // every session alive at the cut-off was revoked with it.
+                if let Some(valid_after) = user.tokens_valid_after {
+                    let cutoff = valid_after.timestamp();
+                    match claims.iat.map(|iat| iat as i64) {
+                        Some(iat) if iat > cutoff || (session_bound && iat == cutoff) => {}
+                        _ => return Err(claims::invalid_token()),
+                    }
                 }
+                Ok(user)
             }
-            Err(_) => {
-                e.add_error("token", "Invalid jwt token");
-                Err(e.into())
-            }
+            _ => Err(claims::invalid_token()),
+        }
+    }
+
+    /// Loads the user of an access token, provided its session is still
+    /// active.
+    pub fn load_from_token(jwt_token: &str, connection: &PgConnection) -> Result<User, ApiError> {
+        let claims = claims::verify(jwt_token)?;
+        let user = User::load_verified(&claims, None, true, connection)?;
+        let session_id = claims
+            .jti
+            .as_ref()
+            .and_then(|jti| jti.parse::<i32>().ok())
+            .ok_or_else(claims::invalid_token)?;
+        Session::check(session_id, user.id, connection)?;
+        Ok(user)
+    }
+
+    pub fn has_two_factor(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.email_verified_at.is_some()
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
+    /// Fails for accounts an admin has suspended.
+    pub fn require_active(&self) -> Result<(), ApiError> {
+        if self.is_suspended() {
+            Err(ApiError::Suspended(self.suspended_reason.clone()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fails for users who have not confirmed their email address yet.
+    pub fn require_verified(&self) -> Result<(), ApiError> {
+        if self.is_verified() {
+            Ok(())
+        } else {
+            Err(ApiError::EmailNotVerified)
         }
     }
 
@@ -112,11 +380,35 @@ impl User {
             .map_err(|e| e.into())
     }
 
+    /// Loads the user going by `name`. If nobody does but someone used to,
+    /// fails with `ApiError::Renamed` so the caller gets redirected.
     pub fn load_by_name(name: &str, connection: &PgConnection) -> Result<User, ApiError> {
+        if let Some(user) = User::find_by_current_name(name, connection)? {
+            return Ok(user);
+        }
+        match history::renamed_to(name, connection)? {
+            Some(user) => Err(ApiError::Renamed {
+                from: name.to_string(),
+                to: user.username,
+            }),
+            None => Err(DieselError::NotFound.into()),
+        }
+    }
+
+    /// Like `load_by_name`, but follows renames instead of reporting them.
+    pub fn find_by_any_name(name: &str, connection: &PgConnection) -> Result<Option<User>, ApiError> {
+        match User::find_by_current_name(name, connection)? {
+            Some(user) => Ok(Some(user)),
+            None => history::renamed_to(name, connection),
+        }
+    }
+
+    fn find_by_current_name(name: &str, connection: &PgConnection) -> Result<Option<User>, ApiError> {
         use db::schema::users::dsl::*;
         users
-            .filter(username.eq(&name))
+            .filter(lower(username).eq(lower(name)))
             .get_result::<User>(connection)
+            .optional()
             .map_err(|e| e.into())
     }
 
@@ -129,10 +421,10 @@ impl User {
     }
 }
 
-#[derive(Deserialize, Insertable, Serialize)]
+#[derive(Insertable)]
 #[table_name = "users"]
 pub struct NewUser {
     pub username: String,
-    pub token: String,
     pub email: String,
+    pub password_hash: String,
 }
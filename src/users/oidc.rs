@@ -0,0 +1,444 @@
+use base64;
+use chrono::{DateTime, Duration, Utc};
+use config::{OidcProvider, CONFIG};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use db::lower;
+use db::schema::{identities, oauth_states, users};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::{delete as diesel_delete, insert_into, update as diesel_update};
+use reqwest;
+use ring::signature;
+use rocket::http::uri::URI;
+use serde::de::DeserializeOwned;
+use serde_json;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration as StdDuration, Instant};
+use types::{ApiError, ValidationError};
+use untrusted::Input;
+use utils::random_token;
+use super::models::{NewUser, User};
+use super::utils::{normalize_email, username_policy, validate_username};
+
+/// How long a user may take at the provider before the state expires.
+const STATE_TTL: i64 = 600;
+/// How long fetched signing keys are trusted before being fetched again.
+const JWKS_TTL: u64 = 3600;
+/// Seconds a single request to the provider may take.
+const HTTP_TIMEOUT: u64 = 10;
+
+#[derive(Debug, Queryable, Identifiable)]
+#[table_name = "identities"]
+pub struct Identity {
+    pub id: i32,
+    pub user_id: i32,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "identities"]
+struct NewIdentity<'a> {
+    user_id: i32,
+    provider: &'a str,
+    subject: &'a str,
+    email: Option<&'a str>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable)]
+struct OAuthState {
+    id: i32,
+    state: String,
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_states"]
+struct NewOAuthState<'a> {
+    state: &'a str,
+    provider: &'a str,
+    code_verifier: &'a str,
+    nonce: &'a str,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorization {
+    authorization_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JoseHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match *self {
+            Audience::One(ref aud) => aud == client_id,
+            Audience::Many(ref auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct IdClaims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    preferred_username: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+lazy_static! {
+    static ref JWKS: Mutex<HashMap<String, (Instant, Vec<Jwk>)>> = Mutex::new(HashMap::new());
+}
+
+fn login_failed(reason: &str) -> ApiError {
+    ValidationError::from("oauth", reason).into()
+}
+
+fn decode_b64(value: &str) -> Result<Vec<u8>, ApiError> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| login_failed("Malformed id token"))
+}
+
+fn decode_segment<T: DeserializeOwned>(segment: &str) -> Result<T, ApiError> {
+    serde_json::from_slice(&decode_b64(segment)?).map_err(|_| login_failed("Malformed id token"))
+}
+
+// A provider that stops answering must not hold a worker forever.
+fn http_client() -> Result<reqwest::Client, ApiError> {
+    reqwest::Client::builder()
+        .timeout(StdDuration::from_secs(HTTP_TIMEOUT))
+        .build()
+        .map_err(|_| ApiError::Internal)
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(verifier);
+    let mut digest = [0u8; 32];
+    hasher.result(&mut digest);
+    base64::encode_config(&digest, base64::URL_SAFE_NO_PAD)
+}
+
+/// Unknown provider names are reported as a missing entity.
+pub fn provider(name: &str) -> Result<&'static OidcProvider, ApiError> {
+    CONFIG
+        .oidc_providers
+        .get(name)
+        .ok_or_else(|| DieselError::NotFound.into())
+}
+
+/// Starts an authorization code + PKCE flow. The verifier and nonce stay on
+/// the server, keyed by the `state` the provider echoes back.
+pub fn authorize(provider: &OidcProvider, conn: &PgConnection) -> Result<Authorization, ApiError> {
+    let state = random_token(16)?;
+    let code_verifier = random_token(32)?;
+    let nonce = random_token(16)?;
+    let now = Utc::now();
+
+    // Flows that were never finished would pile up otherwise.
+    diesel_delete(
+        oauth_states::table.filter(oauth_states::created_at.lt(now - Duration::seconds(STATE_TTL))),
+    ).execute(conn)?;
+    insert_into(oauth_states::table)
+        .values(&NewOAuthState {
+            state: &state,
+            provider: &provider.name,
+            code_verifier: &code_verifier,
+            nonce: &nonce,
+            created_at: now,
+        })
+        .execute(conn)?;
+
+    let authorization_url = format!(
+        "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&nonce={}&code_challenge={}&code_challenge_method=S256",
+        provider.authorization_endpoint,
+        URI::percent_encode(&provider.client_id),
+        URI::percent_encode(&provider.redirect_uri),
+        URI::percent_encode(&provider.scopes),
+        state,
+        nonce,
+        pkce_challenge(&code_verifier)
+    );
+    Ok(Authorization { authorization_url })
+}
+
+/// Finishes the flow started by `authorize` and returns the local user the
+/// provider's identity belongs to.
+pub fn callback(
+    provider: &OidcProvider,
+    code: &str,
+    state: &str,
+    conn: &PgConnection,
+) -> Result<User, ApiError> {
+    // Deleting the row makes every state single use.
+    let pending = diesel_delete(
+        oauth_states::table
+            .filter(oauth_states::state.eq(state))
+            .filter(oauth_states::provider.eq(&provider.name)),
+    ).get_result::<OAuthState>(conn)
+        .optional()?
+        .ok_or_else(|| login_failed("Unknown or already used state"))?;
+    if pending.created_at < Utc::now() - Duration::seconds(STATE_TTL) {
+        return Err(login_failed("Login took too long, please try again"));
+    }
+
+    let id_token = exchange_code(provider, code, &pending.code_verifier)?;
+    let claims = validate_id_token(provider, &id_token, &pending.nonce)?;
+    find_or_link_user(provider, &claims, conn)
+}
+
+fn exchange_code(provider: &OidcProvider, code: &str, verifier: &str) -> Result<String, ApiError> {
+    let mut params = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", provider.redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("code_verifier", verifier),
+    ];
+    if let Some(ref secret) = provider.client_secret {
+        params.push(("client_secret", secret.as_str()));
+    }
+
+    let mut response = http_client()?
+        .post(&provider.token_endpoint)
+        .form(&params)
+        .send()
+        .map_err(|_| ApiError::Internal)?;
+    if !response.status().is_success() {
+        return Err(login_failed("The identity provider rejected the authorization code"));
+    }
+    let tokens = response
+        .json::<TokenResponse>()
+        .map_err(|_| ApiError::Internal)?;
+    Ok(tokens.id_token)
+}
+
+fn fetch_jwks(provider: &OidcProvider) -> Result<Vec<Jwk>, ApiError> {
+    let mut response = http_client()?
+        .get(&provider.jwks_uri)
+        .send()
+        .map_err(|_| ApiError::Internal)?;
+    let jwks = response.json::<JwkSet>().map_err(|_| ApiError::Internal)?;
+    Ok(jwks.keys)
+}
+
+fn find_key(keys: &[Jwk], kid: Option<&str>) -> Option<Jwk> {
+    keys.iter()
+        .filter(|key| key.kty == "RSA")
+        .find(|key| kid.is_none() || key.kid.as_ref().map(|k| k.as_str()) == kid)
+        .cloned()
+}
+
+/// Signing key for `kid`, refetching the provider's key set when the cached
+/// one is stale or does not know the key yet, which happens after rotation.
+/// The cache is not locked during the fetch, so a slow provider only delays
+/// its own logins.
+fn signing_key(provider: &OidcProvider, kid: Option<&str>) -> Result<Jwk, ApiError> {
+    {
+        let cache = JWKS.lock().map_err(|_| ApiError::Internal)?;
+        if let Some(&(fetched_at, ref keys)) = cache.get(&provider.name) {
+            if fetched_at.elapsed() < StdDuration::from_secs(JWKS_TTL) {
+                if let Some(key) = find_key(keys, kid) {
+                    return Ok(key);
+                }
+            }
+        }
+    }
+
+    let keys = fetch_jwks(provider)?;
+    let key = find_key(&keys, kid);
+    JWKS.lock()
+        .map_err(|_| ApiError::Internal)?
+        .insert(provider.name.clone(), (Instant::now(), keys));
+    key.ok_or_else(|| login_failed("Unknown id token signing key"))
+}
+
+fn validate_id_token(provider: &OidcProvider, raw: &str, nonce: &str) -> Result<IdClaims, ApiError> {
+    let parts = raw.split('.').collect::<Vec<&str>>();
+    if parts.len() != 3 {
+        return Err(login_failed("Malformed id token"));
+    }
+
+    let header = decode_segment::<JoseHeader>(parts[0])?;
+    if header.alg != "RS256" {
+        return Err(login_failed("Unsupported id token algorithm"));
+    }
+
+    let key = signing_key(provider, header.kid.as_ref().map(|kid| kid.as_str()))?;
+    let (n, e) = match (&key.n, &key.e) {
+        (&Some(ref n), &Some(ref e)) => (decode_b64(n)?, decode_b64(e)?),
+        _ => return Err(login_failed("Unusable id token signing key")),
+    };
+    let message = format!("{}.{}", parts[0], parts[1]);
+    let signature_bytes = decode_b64(parts[2])?;
+    signature::primitive::verify_rsa(
+        &signature::RSA_PKCS1_2048_8192_SHA256,
+        (Input::from(&n), Input::from(&e)),
+        Input::from(message.as_bytes()),
+        Input::from(&signature_bytes),
+    ).map_err(|_| login_failed("Invalid id token signature"))?;
+
+    let claims = decode_segment::<IdClaims>(parts[1])?;
+    if claims.iss != provider.issuer {
+        return Err(login_failed("Id token was issued by someone else"));
+    }
+    if !claims.aud.contains(&provider.client_id) {
+        return Err(login_failed("Id token was issued for another client"));
+    }
+    if claims.exp <= Utc::now().timestamp() {
+        return Err(login_failed("Expired id token"));
+    }
+    if claims.nonce.as_ref().map(|n| n.as_str()) != Some(nonce) {
+        return Err(login_failed("Id token nonce does not match"));
+    }
+    Ok(claims)
+}
+
+// Derives a free username from what the provider knows about the user.
+fn available_username(claims: &IdClaims, conn: &PgConnection) -> Result<String, ApiError> {
+    // Leaves room for the suffix added when the name is taken.
+    let max_length = CONFIG.username.max_length.saturating_sub(7);
+    let wanted = claims
+        .preferred_username
+        .as_ref()
+        .or(claims.email.as_ref())
+        .map(|name| name.split('@').next().unwrap_or(""))
+        .unwrap_or("")
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(max_length)
+        .collect::<String>();
+    let (base, mut candidate) = if username_policy(&wanted).is_ok() {
+        (wanted.clone(), wanted)
+    } else {
+        ("user".to_string(), format!("user-{}", &random_token(3)?))
+    };
+
+    loop {
+        match validate_username(&candidate, None, conn) {
+            Ok(_) => return Ok(candidate),
+            Err(ApiError::Validation(_)) => {
+                candidate = format!("{}-{}", base, &random_token(3)?);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns the user linked to this identity. An identity seen for the first
+/// time is linked to the account with the same email if both the provider
+/// and we have verified that address, and gets a new account if there is
+/// none. An unverified account may have been registered by someone else to
+/// take over the real owner's login, so it is never linked.
+fn find_or_link_user(
+    provider: &OidcProvider,
+    claims: &IdClaims,
+    conn: &PgConnection,
+) -> Result<User, ApiError> {
+    conn.transaction::<_, ApiError, _>(|| {
+        let identity = identities::table
+            .filter(identities::provider.eq(&provider.name))
+            .filter(identities::subject.eq(&claims.sub))
+            .first::<Identity>(conn)
+            .optional()?;
+        if let Some(identity) = identity {
+            return User::load_by_id(&identity.user_id, conn);
+        }
+
+        let email = claims
+            .email
+            .as_ref()
+            .ok_or_else(|| login_failed("The identity provider did not share an email address"))?;
+        if !claims.email_verified.unwrap_or(false) {
+            return Err(login_failed(
+                "The identity provider has not verified this email address",
+            ));
+        }
+
+        let email = normalize_email(email);
+        let existing = users::table
+            .filter(lower(users::email).eq(&email))
+            .first::<User>(conn)
+            .optional()?;
+        let user = match existing {
+            Some(ref user) if !user.is_verified() => {
+                return Err(login_failed(
+                    "An account with this email exists but has not been verified, sign in with its password and verify it first",
+                ));
+            }
+            Some(user) => user,
+            None => {
+                // Nobody knows this password; the user can set one through
+                // the password reset flow if they ever want to.
+                let new_user = NewUser {
+                    username: available_username(claims, conn)?,
+                    email: email.clone(),
+                    password_hash: User::make_password(&random_token(32)?)?,
+                };
+                insert_into(users::table)
+                    .values(&new_user)
+                    .get_result::<User>(conn)?
+            }
+        };
+
+        if !user.is_verified() {
+            diesel_update(&user)
+                .set(users::email_verified_at.eq(Utc::now()))
+                .execute(conn)?;
+        }
+
+        insert_into(identities::table)
+            .values(&NewIdentity {
+                user_id: user.id,
+                provider: &provider.name,
+                subject: &claims.sub,
+                email: Some(email.as_str()),
+                created_at: Utc::now(),
+            })
+            .execute(conn)?;
+
+        User::load_by_id(&user.id, conn)
+    })
+}
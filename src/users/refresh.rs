@@ -0,0 +1,142 @@
+use chrono::{DateTime, Duration, Utc};
+use config::CONFIG;
+use db::schema::{refresh_tokens, sessions};
+use diesel::prelude::*;
+use diesel::{insert_into, update as diesel_update};
+use types::ApiError;
+use utils::{random_token, sha256_hex};
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub family: String,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub replaced_by: Option<i32>,
+}
+
+#[derive(Insertable)]
+#[table_name = "refresh_tokens"]
+struct NewRefreshToken<'a> {
+    user_id: i32,
+    family: &'a str,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl RefreshToken {
+    /// Starts a new token family for `user_id` and returns its first token
+    /// together with the plain token.
+    pub fn issue(user_id: i32, conn: &PgConnection) -> Result<(RefreshToken, String), ApiError> {
+        let family = random_token(16)?;
+        RefreshToken::issue_in_family(user_id, &family, conn)
+    }
+
+    fn issue_in_family(
+        user_id: i32,
+        family: &str,
+        conn: &PgConnection,
+    ) -> Result<(RefreshToken, String), ApiError> {
+        let token = random_token(32)?;
+        let now = Utc::now();
+        let new_token = NewRefreshToken {
+            user_id,
+            family,
+            token_hash: sha256_hex(&token),
+            created_at: now,
+            expires_at: now + Duration::seconds(CONFIG.jwt.refresh_ttl as i64),
+        };
+        let stored = insert_into(refresh_tokens::table)
+            .values(&new_token)
+            .get_result::<RefreshToken>(conn)?;
+        Ok((stored, token))
+    }
+
+    fn find(token: &str, conn: &PgConnection) -> Result<Option<RefreshToken>, ApiError> {
+        refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(sha256_hex(token)))
+            .for_update()
+            .first::<RefreshToken>(conn)
+            .optional()
+            .map_err(|e| e.into())
+    }
+
+    /// Exchanges `token` for its successor and returns the successor together
+    /// with its plain token. A token that was already rotated has leaked, so
+    /// presenting it again revokes the whole family.
+    pub fn rotate(token: &str, conn: &PgConnection) -> Result<(RefreshToken, String), ApiError> {
+        // Runs as `Ok(None)` on rejection so that a family revocation is
+        // committed rather than rolled back with the error.
+        let rotated = conn.transaction::<_, ApiError, _>(|| {
+            let current = match RefreshToken::find(token, conn)? {
+                Some(current) => current,
+                None => return Ok(None),
+            };
+
+            if current.revoked_at.is_some() {
+                RefreshToken::revoke_family(&current.family, conn)?;
+                return Ok(None);
+            }
+
+            if current.expires_at <= Utc::now() {
+                return Ok(None);
+            }
+
+            let (next, plain) = RefreshToken::issue_in_family(current.user_id, &current.family, conn)?;
+            diesel_update(&current)
+                .set((
+                    refresh_tokens::revoked_at.eq(Utc::now()),
+                    refresh_tokens::replaced_by.eq(next.id),
+                ))
+                .execute(conn)?;
+            Ok(Some((next, plain)))
+        })?;
+        rotated.ok_or(ApiError::Unauthorized)
+    }
+
+    /// Revokes the family `token` belongs to. Unknown tokens are ignored.
+    pub fn revoke(token: &str, conn: &PgConnection) -> Result<(), ApiError> {
+        if let Some(current) = RefreshToken::find(token, conn)? {
+            RefreshToken::revoke_family(&current.family, conn)?;
+        }
+        Ok(())
+    }
+
+    /// Revokes every token of `family` and ends the session it backs.
+    pub fn revoke_family(family: &str, conn: &PgConnection) -> Result<usize, ApiError> {
+        diesel_update(
+            sessions::table
+                .filter(sessions::family.eq(family))
+                .filter(sessions::revoked_at.is_null()),
+        ).set(sessions::revoked_at.eq(Utc::now()))
+            .execute(conn)?;
+        diesel_update(
+            refresh_tokens::table
+                .filter(refresh_tokens::family.eq(family))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        ).set(refresh_tokens::revoked_at.eq(Utc::now()))
+            .execute(conn)
+            .map_err(|e| e.into())
+    }
+
+    /// Revokes every refresh token of `user_id`, signing out all devices.
+    pub fn revoke_all(user_id: i32, conn: &PgConnection) -> Result<usize, ApiError> {
+        diesel_update(
+            sessions::table
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::revoked_at.is_null()),
+        ).set(sessions::revoked_at.eq(Utc::now()))
+            .execute(conn)?;
+        diesel_update(
+            refresh_tokens::table
+                .filter(refresh_tokens::user_id.eq(user_id))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        ).set(refresh_tokens::revoked_at.eq(Utc::now()))
+            .execute(conn)
+            .map_err(|e| e.into())
+    }
+}
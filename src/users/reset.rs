@@ -0,0 +1,119 @@
+use chrono::{DateTime, Duration, Utc};
+use config::CONFIG;
+use db::lower;
+use db::schema::{password_resets, users};
+use diesel::prelude::*;
+use diesel::{insert_into, update as diesel_update};
+use mailer::{app_link, Mail, Mailer};
+use types::{ApiError, ValidationError};
+use utils::{random_token, sha256_hex};
+use super::models::User;
+use super::refresh::RefreshToken;
+use super::throttle::LoginThrottle;
+use super::tokens::PersonalToken;
+use super::utils::validate_password;
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct PasswordReset {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_resets"]
+struct NewPasswordReset {
+    user_id: i32,
+    token_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+fn invalid_reset() -> ApiError {
+    ValidationError::from("token", "Invalid or expired reset token").into()
+}
+
+impl PasswordReset {
+    /// Mails a reset link to the owner of `email`, which must already be
+    /// normalized. Unknown addresses are
+    /// silently ignored so the endpoint cannot be used to probe for accounts.
+    pub fn request(email: &str, conn: &PgConnection, mailer: &Mailer) -> Result<(), ApiError> {
+        let user = users::table
+            .filter(lower(users::email).eq(email))
+            .first::<User>(conn)
+            .optional()?;
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        let token = random_token(32)?;
+        let now = Utc::now();
+        let new_reset = NewPasswordReset {
+            user_id: user.id,
+            token_hash: sha256_hex(&token),
+            created_at: now,
+            expires_at: now + Duration::seconds(CONFIG.password.reset_ttl),
+        };
+
+        conn.transaction::<_, ApiError, _>(|| {
+            insert_into(password_resets::table)
+                .values(&new_reset)
+                .execute(conn)?;
+
+            let link = app_link(&format!("/reset-password?token={}", token));
+            let mail = Mail {
+                to: user.email.clone(),
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\nsomeone asked to reset your password. If it was you, open this link:\n{}\n\nOtherwise you can ignore this mail.\n",
+                    user.username, link
+                ),
+            };
+            mailer.send(&mail)
+        })
+    }
+
+    /// Sets a new password for the owner of `token`, burns every outstanding
+    /// reset token and signs the user out everywhere, personal access tokens
+    /// included.
+    pub fn reset(token: &str, password: &String, conn: &PgConnection) -> Result<(), ApiError> {
+        conn.transaction::<_, ApiError, _>(|| {
+            let reset = password_resets::table
+                .filter(password_resets::token_hash.eq(sha256_hex(token)))
+                .for_update()
+                .first::<PasswordReset>(conn)
+                .optional()?
+                .ok_or_else(invalid_reset)?;
+            let now = Utc::now();
+            if reset.used_at.is_some() || reset.expires_at <= now {
+                return Err(invalid_reset());
+            }
+
+            let mut user = User::load_by_id(&reset.user_id, conn)?;
+            validate_password(password, &[user.username.as_str(), user.email.as_str()])?;
+            user.new_password(password)?;
+            diesel_update(&user)
+                .set((
+                    users::password_hash.eq(&user.password_hash),
+                    users::tokens_valid_after.eq(now),
+                ))
+                .execute(conn)?;
+
+            diesel_update(
+                password_resets::table
+                    .filter(password_resets::user_id.eq(user.id))
+                    .filter(password_resets::used_at.is_null()),
+            ).set(password_resets::used_at.eq(now))
+                .execute(conn)?;
+
+            RefreshToken::revoke_all(user.id, conn)?;
+            PersonalToken::revoke_all(user.id, conn)?;
+            LoginThrottle::clear_account(&user.email, conn)?;
+            Ok(())
+        })
+    }
+}
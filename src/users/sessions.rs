@@ -0,0 +1,252 @@
+use chrono::{DateTime, Duration, Utc};
+use db::schema::sessions;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::{insert_into, update as diesel_update};
+use rocket::request::{self, FromRequest, Request};
+use rocket::Outcome;
+use types::ApiError;
+use super::claims;
+use super::parse_authorization;
+use super::refresh::RefreshToken;
+
+// Seeing a session is recorded at most this often, so that every
+// authenticated request does not turn into a write.
+const TOUCH_INTERVAL: i64 = 60;
+
+/// One login of a user, backed by a refresh token family. Access tokens
+/// carry the session id as their `jti` and stop working once it is revoked.
+#[derive(Debug, Queryable, Identifiable)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub family: String,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+struct NewSession<'a> {
+    user_id: i32,
+    family: &'a str,
+    device_name: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    ip: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionView {
+    id: i32,
+    device_name: Option<String>,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionList {
+    sessions: Vec<SessionView>,
+}
+
+/// What the client tells about itself when signing in. The device name is
+/// taken from the optional `X-Device-Name` header.
+pub struct Device {
+    name: Option<String>,
+    user_agent: Option<String>,
+    ip: Option<String>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Device {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Device, ()> {
+        let headers = request.headers();
+        Outcome::Success(Device {
+            name: headers.get_one("X-Device-Name").map(|name| name.to_string()),
+            user_agent: headers.get_one("User-Agent").map(|agent| agent.to_string()),
+            ip: request.remote().map(|addr| addr.ip().to_string()),
+        })
+    }
+}
+
+/// An access token a request was made with.
+pub struct AccessToken {
+    pub session_id: i32,
+    pub token: String,
+    pub expires_at: u64,
+}
+
+/// The request's access token, if it belongs to a session.
+pub struct CurrentSession(pub Option<AccessToken>);
+
+impl CurrentSession {
+    pub fn id(&self) -> Option<i32> {
+        self.0.as_ref().map(|access_token| access_token.session_id)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for CurrentSession {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<CurrentSession, ()> {
+        let access_token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(parse_authorization)
+            .and_then(|token| {
+                let claims = claims::verify(token).ok()?;
+                Some(AccessToken {
+                    session_id: claims.jti?.parse::<i32>().ok()?,
+                    token: token.to_string(),
+                    expires_at: claims.exp?,
+                })
+            });
+        Outcome::Success(CurrentSession(access_token))
+    }
+}
+
+impl Session {
+    /// Signs `user_id` in on `device`: starts a refresh token family and the
+    /// session backed by it. Returns the session and the plain refresh token.
+    pub fn start(
+        user_id: i32,
+        device: &Device,
+        conn: &PgConnection,
+    ) -> Result<(Session, String), ApiError> {
+        conn.transaction::<_, ApiError, _>(|| {
+            let (refresh_token, plain) = RefreshToken::issue(user_id, conn)?;
+            let session = Session::insert(user_id, &refresh_token.family, device, conn)?;
+            Ok((session, plain))
+        })
+    }
+
+    fn insert(
+        user_id: i32,
+        family: &str,
+        device: &Device,
+        conn: &PgConnection,
+    ) -> Result<Session, ApiError> {
+        let now = Utc::now();
+        let new_session = NewSession {
+            user_id,
+            family,
+            device_name: device.name.as_ref().map(|name| name.as_str()),
+            user_agent: device.user_agent.as_ref().map(|agent| agent.as_str()),
+            ip: device.ip.clone(),
+            created_at: now,
+            last_seen_at: now,
+        };
+        insert_into(sessions::table)
+            .values(&new_session)
+            .get_result::<Session>(conn)
+            .map_err(|e| e.into())
+    }
+
+    /// Session backed by `refresh_token`, noting that `device` just used it.
+    /// Families issued before sessions existed get one on their first
+    /// refresh.
+    pub fn refreshed(
+        refresh_token: &RefreshToken,
+        device: &Device,
+        conn: &PgConnection,
+    ) -> Result<Session, ApiError> {
+        let session = sessions::table
+            .filter(sessions::family.eq(&refresh_token.family))
+            .first::<Session>(conn)
+            .optional()?;
+        match session {
+            Some(session) => diesel_update(&session)
+                .set((
+                    sessions::last_seen_at.eq(Utc::now()),
+                    sessions::ip.eq(&device.ip),
+                ))
+                .get_result::<Session>(conn)
+                .map_err(|e| e.into()),
+            None => Session::insert(refresh_token.user_id, &refresh_token.family, device, conn),
+        }
+    }
+
+    /// Fails unless session `id` belongs to `user_id` and is still active.
+    pub fn check(id: i32, user_id: i32, conn: &PgConnection) -> Result<(), ApiError> {
+        let session = sessions::table
+            .find(id)
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked_at.is_null())
+            .first::<Session>(conn)
+            .optional()?
+            .ok_or_else(claims::invalid_token)?;
+
+        let now = Utc::now();
+        if now - session.last_seen_at > Duration::seconds(TOUCH_INTERVAL) {
+            diesel_update(&session)
+                .set(sessions::last_seen_at.eq(now))
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    /// Active sessions of `user_id`, most recently used first.
+    pub fn list(
+        user_id: i32,
+        current: Option<i32>,
+        conn: &PgConnection,
+    ) -> Result<SessionList, ApiError> {
+        let sessions = sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked_at.is_null())
+            .order(sessions::last_seen_at.desc())
+            .load::<Session>(conn)?
+            .into_iter()
+            .map(|session| SessionView {
+                current: Some(session.id) == current,
+                id: session.id,
+                device_name: session.device_name,
+                user_agent: session.user_agent,
+                ip: session.ip,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+            })
+            .collect();
+        Ok(SessionList { sessions })
+    }
+
+    /// Signs session `id` of `user_id` out. Other users' sessions are
+    /// reported as not found.
+    pub fn revoke(user_id: i32, id: i32, conn: &PgConnection) -> Result<(), ApiError> {
+        let session = sessions::table
+            .find(id)
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked_at.is_null())
+            .first::<Session>(conn)
+            .optional()?
+            .ok_or(DieselError::NotFound)?;
+        RefreshToken::revoke_family(&session.family, conn)?;
+        Ok(())
+    }
+
+    /// Signs every session of `user_id` out except `current`.
+    pub fn revoke_others(user_id: i32, current: i32, conn: &PgConnection) -> Result<(), ApiError> {
+        let families = sessions::table
+            .select(sessions::family)
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::id.ne(current))
+            .filter(sessions::revoked_at.is_null())
+            .load::<String>(conn)?;
+        conn.transaction::<_, ApiError, _>(|| {
+            for family in &families {
+                RefreshToken::revoke_family(family, conn)?;
+            }
+            Ok(())
+        })
+    }
+}
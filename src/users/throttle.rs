@@ -0,0 +1,198 @@
+use chrono::{DateTime, Duration, Utc};
+use config::CONFIG;
+use db::schema::login_throttles;
+use diesel::prelude::*;
+use diesel::{delete as diesel_delete, insert_into, update as diesel_update};
+use std::cmp::{max, min};
+use std::net::IpAddr;
+use types::ApiError;
+
+pub const ACCOUNT: &str = "account";
+pub const ACCOUNT_CLIENT_IP: &str = "account_ip";
+pub const CLIENT_IP: &str = "ip";
+pub const MAIL: &str = "mail";
+pub const MAIL_CLIENT_IP: &str = "mail_ip";
+
+#[derive(Debug, Queryable, Identifiable)]
+pub struct LoginThrottle {
+    pub id: i32,
+    pub scope: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure_at: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+// Every login attempt counts against the account it targets, against the
+// address it comes from and against the pair of both. Only the pair and the
+// address get locked out for long, the account on its own is merely slowed
+// down, so failing logins from elsewhere cannot lock its owner out.
+fn subjects(email: &str, ip: Option<IpAddr>) -> Vec<(&'static str, String)> {
+    let mut subjects = vec![(ACCOUNT, email.to_string())];
+    if let Some(ip) = ip {
+        subjects.push((ACCOUNT_CLIENT_IP, account_client(email, ip)));
+        subjects.push((CLIENT_IP, ip.to_string()));
+    }
+    subjects
+}
+
+fn account_client(email: &str, ip: IpAddr) -> String {
+    format!("{}|{}", ip, email)
+}
+
+// Mails asked for are counted separately, so requesting links never locks
+// anyone out of logging in.
+fn mail_subjects(email: &str, ip: Option<IpAddr>) -> Vec<(&'static str, String)> {
+    let mut subjects = vec![(MAIL, email.to_string())];
+    if let Some(ip) = ip {
+        subjects.push((MAIL_CLIENT_IP, ip.to_string()));
+    }
+    subjects
+}
+
+fn threshold(scope: &str) -> i32 {
+    match scope {
+        CLIENT_IP | MAIL_CLIENT_IP => CONFIG.login_throttle.ip_threshold,
+        MAIL => CONFIG.login_throttle.mail_threshold,
+        _ => CONFIG.login_throttle.account_threshold,
+    }
+}
+
+fn max_lockout(scope: &str) -> i64 {
+    match scope {
+        ACCOUNT => CONFIG.login_throttle.account_max_delay,
+        _ => CONFIG.login_throttle.max_lockout,
+    }
+}
+
+impl LoginThrottle {
+    /// Fails with `TooManyRequests` while the account is slowed down or the
+    /// client address, alone or for this account, is locked out.
+    pub fn check(email: &str, ip: Option<IpAddr>, conn: &PgConnection) -> Result<(), ApiError> {
+        check_subjects(&subjects(email, ip), conn)
+    }
+
+    /// Counts a failed attempt and locks the subject once it crosses its
+    /// threshold. Each failure past the threshold doubles the lockout.
+    pub fn record_failure(
+        email: &str,
+        ip: Option<IpAddr>,
+        conn: &PgConnection,
+    ) -> Result<(), ApiError> {
+        count(&subjects(email, ip), conn)
+    }
+
+    /// Counts a mail sent to `email` on behalf of `ip`, refusing with
+    /// `TooManyRequests` while either has asked for too many lately.
+    pub fn record_mail(email: &str, ip: Option<IpAddr>, conn: &PgConnection) -> Result<(), ApiError> {
+        let subjects = mail_subjects(email, ip);
+        check_subjects(&subjects, conn)?;
+        count(&subjects, conn)
+    }
+
+    /// Forgets the failures of a login that just succeeded.
+    pub fn succeeded(email: &str, ip: Option<IpAddr>, conn: &PgConnection) -> Result<(), ApiError> {
+        LoginThrottle::clear(ACCOUNT, email, conn)?;
+        if let Some(ip) = ip {
+            LoginThrottle::clear(ACCOUNT_CLIENT_IP, &account_client(email, ip), conn)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets failures and lockouts of `email` from every address, for when
+    /// its password changes or the account goes away.
+    pub fn clear_account(email: &str, conn: &PgConnection) -> Result<(), ApiError> {
+        LoginThrottle::clear(ACCOUNT, email, conn)?;
+        let escaped = email
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        diesel_delete(
+            login_throttles::table
+                .filter(login_throttles::scope.eq(ACCOUNT_CLIENT_IP))
+                .filter(login_throttles::subject.like(format!("%|{}", escaped))),
+        ).execute(conn)?;
+        Ok(())
+    }
+
+    /// Forgets failures and any lockout for `subject`.
+    pub fn clear(scope: &str, subject: &str, conn: &PgConnection) -> Result<usize, ApiError> {
+        diesel_delete(
+            login_throttles::table
+                .filter(login_throttles::scope.eq(scope))
+                .filter(login_throttles::subject.eq(subject)),
+        ).execute(conn)
+            .map_err(|e| e.into())
+    }
+}
+
+fn check_subjects(subjects: &[(&str, String)], conn: &PgConnection) -> Result<(), ApiError> {
+    let now = Utc::now();
+    for &(scope, ref subject) in subjects {
+        let locked_until = login_throttles::table
+            .select(login_throttles::locked_until)
+            .filter(login_throttles::scope.eq(scope))
+            .filter(login_throttles::subject.eq(subject))
+            .first::<Option<DateTime<Utc>>>(conn)
+            .optional()?;
+        if let Some(Some(until)) = locked_until {
+            if until > now {
+                return Err(ApiError::TooManyRequests((until - now).num_seconds() + 1));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn count(subjects: &[(&str, String)], conn: &PgConnection) -> Result<(), ApiError> {
+    let config = &CONFIG.login_throttle;
+    conn.transaction::<_, ApiError, _>(|| {
+        let now = Utc::now();
+        for &(scope, ref subject) in subjects {
+            insert_into(login_throttles::table)
+                .values((
+                    login_throttles::scope.eq(scope),
+                    login_throttles::subject.eq(subject),
+                    login_throttles::failures.eq(0),
+                    login_throttles::last_failure_at.eq(now),
+                ))
+                .on_conflict((login_throttles::scope, login_throttles::subject))
+                .do_nothing()
+                .execute(conn)?;
+
+            let throttle = login_throttles::table
+                .filter(login_throttles::scope.eq(scope))
+                .filter(login_throttles::subject.eq(subject))
+                .for_update()
+                .first::<LoginThrottle>(conn)?;
+
+            let quiet_since = match throttle.locked_until {
+                Some(until) => max(until, throttle.last_failure_at),
+                None => throttle.last_failure_at,
+            };
+            let failures = if quiet_since < now - Duration::seconds(config.window) {
+                1
+            } else {
+                throttle.failures + 1
+            };
+
+            let threshold = threshold(scope);
+            let locked_until = if failures >= threshold {
+                let doublings = min(failures - threshold, 16) as u32;
+                let lockout = min(config.base_lockout * 2i64.pow(doublings), max_lockout(scope));
+                Some(now + Duration::seconds(lockout))
+            } else {
+                None
+            };
+
+            diesel_update(&throttle)
+                .set((
+                    login_throttles::failures.eq(failures),
+                    login_throttles::last_failure_at.eq(now),
+                    login_throttles::locked_until.eq(locked_until),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
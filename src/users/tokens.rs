@@ -0,0 +1,158 @@
+use chrono::{DateTime, Utc};
+use db::schema::{personal_tokens, users};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::{delete as diesel_delete, insert_into, update as diesel_update};
+use policy::Scope;
+use types::{ApiError, ValidationError};
+use utils::{random_token, sha256_hex};
+use super::claims;
+use super::models::User;
+
+/// Marks personal access tokens so the auth guard can tell them apart from
+/// JWTs, which always start with `eyJ`.
+pub const PREFIX: &str = "pat_";
+
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonalToken {
+    pub id: i32,
+    #[serde(skip_serializing)]
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "personal_tokens"]
+struct NewPersonalToken<'a> {
+    user_id: i32,
+    name: &'a str,
+    token_hash: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    token: PersonalToken,
+    /// The plain token, only ever shown in this response.
+    secret: String,
+}
+
+impl PersonalToken {
+    /// Mints a token for `user_id` and returns it together with the plain
+    /// token.
+    pub fn create(
+        user_id: i32,
+        name: &str,
+        scopes: &[String],
+        expires_at: Option<DateTime<Utc>>,
+        conn: &PgConnection,
+    ) -> Result<CreatedToken, ApiError> {
+        let mut error = ValidationError::default();
+        if name.trim().is_empty() {
+            error.add_error("name", "Token name must not be empty");
+        }
+        if scopes.is_empty() {
+            error.add_error("scopes", "At least one scope is required");
+        }
+        for scope in scopes.iter().filter(|s| Scope::from_name(s).is_none()) {
+            error.add_error("scopes", format!("Unknown scope: {}", scope));
+        }
+        if let Some(expires_at) = expires_at {
+            if expires_at <= Utc::now() {
+                error.add_error("expiresAt", "Expiry must be in the future");
+            }
+        }
+        if !error.empty() {
+            return Err(error.into());
+        }
+
+        let plain = format!("{}{}", PREFIX, random_token(32)?);
+        let new_token = NewPersonalToken {
+            user_id,
+            name: name.trim(),
+            token_hash: sha256_hex(&plain),
+            scopes: scopes.to_vec(),
+            created_at: Utc::now(),
+            expires_at,
+        };
+        let token = insert_into(personal_tokens::table)
+            .values(&new_token)
+            .get_result::<PersonalToken>(conn)?;
+        Ok(CreatedToken {
+            token,
+            secret: plain,
+        })
+    }
+
+    pub fn list(user_id: i32, conn: &PgConnection) -> Result<Vec<PersonalToken>, ApiError> {
+        personal_tokens::table
+            .filter(personal_tokens::user_id.eq(user_id))
+            .order(personal_tokens::created_at.desc())
+            .load::<PersonalToken>(conn)
+            .map_err(|e| e.into())
+    }
+
+    /// Deletes token `id` of `user_id`. Other users' tokens are reported as
+    /// not found.
+    pub fn revoke(user_id: i32, id: i32, conn: &PgConnection) -> Result<(), ApiError> {
+        let deleted = diesel_delete(
+            personal_tokens::table
+                .filter(personal_tokens::id.eq(id))
+                .filter(personal_tokens::user_id.eq(user_id)),
+        ).execute(conn)?;
+        match deleted {
+            0 => Err(DieselError::NotFound.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Deletes every token of `user_id`.
+    pub fn revoke_all(user_id: i32, conn: &PgConnection) -> Result<usize, ApiError> {
+        diesel_delete(personal_tokens::table.filter(personal_tokens::user_id.eq(user_id)))
+            .execute(conn)
+            .map_err(|e| e.into())
+    }
+
+    fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|s| s == scope.name())
+    }
+
+    /// Loads the owner of the plain token `raw`, provided the token is still
+    /// valid and carries `scope`.
+    pub fn authenticate(
+        raw: &str,
+        scope: Option<Scope>,
+        conn: &PgConnection,
+    ) -> Result<User, ApiError> {
+        let token = personal_tokens::table
+            .filter(personal_tokens::token_hash.eq(sha256_hex(raw)))
+            .first::<PersonalToken>(conn)
+            .optional()?
+            .ok_or_else(claims::invalid_token)?;
+        if token.expires_at.map_or(false, |expires_at| expires_at <= Utc::now()) {
+            return Err(claims::invalid_token());
+        }
+
+        match scope {
+            Some(scope) if token.has_scope(scope) => {}
+            _ => return Err(ApiError::MissingScope(scope)),
+        }
+
+        diesel_update(&token)
+            .set(personal_tokens::last_used_at.eq(Utc::now()))
+            .execute(conn)?;
+        users::table
+            .find(token.user_id)
+            .get_result::<User>(conn)
+            .map_err(|e| e.into())
+    }
+}
@@ -0,0 +1,269 @@
+use base32::{self, Alphabet};
+use chrono::{DateTime, Utc};
+use config::CONFIG;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use db::schema::{recovery_codes, users};
+use diesel::prelude::*;
+use diesel::{delete as diesel_delete, insert_into, update as diesel_update};
+use rocket::http::uri::URI;
+use std::net::IpAddr;
+use types::{ApiError, ValidationError};
+use utils::{random_bytes, random_token, sha256_hex};
+use super::models::User;
+use super::throttle::LoginThrottle;
+
+// RFC 6238 defaults, which is what every authenticator app expects.
+const STEP: i64 = 30;
+const DIGITS: usize = 6;
+const RECOVERY_CODES: usize = 10;
+const ALPHABET: Alphabet = Alphabet::RFC4648 { padding: false };
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Enrollment {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Challenge {
+    second_factor_required: bool,
+    challenge_token: String,
+}
+
+fn invalid_code() -> ApiError {
+    ValidationError::from("code", "Invalid two-factor code").into()
+}
+
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut counter = [0u8; 8];
+    for i in 0..8 {
+        counter[7 - i] = (step >> (8 * i)) as u8;
+    }
+
+    let mut hmac = Hmac::new(Sha1::new(), secret);
+    hmac.input(&counter);
+    let result = hmac.result();
+    let digest = result.code();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = ((digest[offset] & 0x7f) as u32) << 24 | (digest[offset + 1] as u32) << 16
+        | (digest[offset + 2] as u32) << 8 | digest[offset + 3] as u32;
+    binary % 10u32.pow(DIGITS as u32)
+}
+
+/// Time step `code` belongs to, allowing one step of clock drift either way.
+fn matching_step(secret: &str, code: &str) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS {
+        return None;
+    }
+    let code = code.parse::<u32>().ok()?;
+    let secret = base32::decode(ALPHABET, secret)?;
+    let current = Utc::now().timestamp() / STEP;
+    (current - 1..current + 2).find(|step| code_at(&secret, *step) == code)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn otpauth_uri(user: &User, secret: &str) -> String {
+    let label = format!("{}:{}", CONFIG.totp_issuer, user.email);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        URI::percent_encode(&label),
+        secret,
+        URI::percent_encode(&CONFIG.totp_issuer),
+        DIGITS,
+        STEP
+    )
+}
+
+/// Stores a fresh pending secret. It only takes effect once `confirm` has
+/// seen a code generated from it.
+pub fn enroll(user: &User, conn: &PgConnection) -> Result<Enrollment, ApiError> {
+    if user.has_two_factor() {
+        let error = ValidationError::from("2fa", "Two-factor authentication is already enabled");
+        return Err(error.into());
+    }
+
+    let secret = base32::encode(ALPHABET, &random_bytes(20)?);
+    diesel_update(user)
+        .set((
+            users::totp_secret.eq(&secret),
+            users::totp_last_step.eq(None::<i64>),
+        ))
+        .execute(conn)?;
+
+    Ok(Enrollment {
+        otpauth_uri: otpauth_uri(user, &secret),
+        secret,
+    })
+}
+
+/// Enables two-factor authentication and hands out a new set of recovery
+/// codes. The plain codes are never shown again.
+pub fn confirm(user: &User, code: &str, conn: &PgConnection) -> Result<RecoveryCodes, ApiError> {
+    let step = match (&user.totp_secret, user.has_two_factor()) {
+        (&Some(ref secret), false) => matching_step(secret, code).ok_or_else(invalid_code)?,
+        _ => {
+            let error = ValidationError::from("2fa", "No pending two-factor enrollment");
+            return Err(error.into());
+        }
+    };
+
+    let codes = (0..RECOVERY_CODES)
+        .map(|_| random_token(8))
+        .collect::<Result<Vec<String>, _>>()?;
+
+    conn.transaction::<_, ApiError, _>(|| {
+        diesel_update(user)
+            .set((
+                users::totp_enabled_at.eq(Utc::now()),
+                users::totp_last_step.eq(step),
+            ))
+            .execute(conn)?;
+
+        diesel_delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+        let rows = codes
+            .iter()
+            .map(|code| {
+                (
+                    recovery_codes::user_id.eq(user.id),
+                    recovery_codes::code_hash.eq(sha256_hex(code)),
+                )
+            })
+            .collect::<Vec<_>>();
+        insert_into(recovery_codes::table)
+            .values(&rows)
+            .execute(conn)?;
+        Ok(())
+    })?;
+
+    Ok(RecoveryCodes {
+        recovery_codes: codes,
+    })
+}
+
+/// Accepts either a current TOTP code or an unused recovery code. Both are
+/// burned on success.
+pub fn verify(user: &User, code: &str, conn: &PgConnection) -> Result<(), ApiError> {
+    let secret = match (&user.totp_secret, user.has_two_factor()) {
+        (&Some(ref secret), true) => secret,
+        _ => return Err(invalid_code()),
+    };
+
+    if let Some(step) = matching_step(secret, code) {
+        let accepted = diesel_update(
+            users::table.find(user.id).filter(
+                users::totp_last_step
+                    .is_null()
+                    .or(users::totp_last_step.lt(step)),
+            ),
+        ).set(users::totp_last_step.eq(step))
+            .execute(conn)?;
+        return if accepted == 1 {
+            Ok(())
+        } else {
+            Err(invalid_code())
+        };
+    }
+
+    let used = diesel_update(
+        recovery_codes::table
+            .filter(recovery_codes::user_id.eq(user.id))
+            .filter(recovery_codes::code_hash.eq(sha256_hex(&normalize_recovery_code(code))))
+            .filter(recovery_codes::used_at.is_null()),
+    ).set(recovery_codes::used_at.eq(Utc::now()))
+        .execute(conn)?;
+    if used == 1 {
+        Ok(())
+    } else {
+        Err(invalid_code())
+    }
+}
+
+/// Turns two-factor authentication off and forgets the recovery codes. Takes
+/// the password as well as a code, and failures count towards the login
+/// lockout, so a stolen token is not enough to guess a code.
+pub fn disable(
+    user: &User,
+    password: &String,
+    code: &str,
+    ip: Option<IpAddr>,
+    conn: &PgConnection,
+) -> Result<(), ApiError> {
+    LoginThrottle::check(&user.email, ip, conn)?;
+    let confirmed = if user.verify_password(password)? {
+        verify(user, code, conn)
+    } else {
+        Err(ValidationError::from("password", "Invalid password").into())
+    };
+    if let Err(e) = confirmed {
+        LoginThrottle::record_failure(&user.email, ip, conn)?;
+        return Err(e);
+    }
+
+    conn.transaction::<_, ApiError, _>(|| {
+        diesel_update(user)
+            .set((
+                users::totp_secret.eq(None::<String>),
+                users::totp_enabled_at.eq(None::<DateTime<Utc>>),
+                users::totp_last_step.eq(None::<i64>),
+            ))
+            .execute(conn)?;
+        diesel_delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+pub fn challenge(user: &User) -> Result<Challenge, ApiError> {
+    Ok(Challenge {
+        second_factor_required: true,
+        challenge_token: user.login_challenge_token()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{code_at, DIGITS, STEP};
+
+    // RFC 6238 appendix B, SHA-1. The RFC lists 8 digit codes, ours are
+    // their last `DIGITS` digits.
+    const SECRET: &[u8] = b"12345678901234567890";
+    const VECTORS: &[(i64, u32)] = &[
+        (59, 94287082),
+        (1111111109, 7081804),
+        (1111111111, 14050471),
+        (1234567890, 89005924),
+        (2000000000, 69279037),
+        (20000000000, 65353130),
+    ];
+
+    #[test]
+    fn code_at_matches_rfc_6238() {
+        for &(time, code) in VECTORS {
+            assert_eq!(
+                code_at(SECRET, time / STEP),
+                code % 10u32.pow(DIGITS as u32),
+                "at {}",
+                time
+            );
+        }
+    }
+}
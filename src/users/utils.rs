@@ -1,4 +1,10 @@
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
 use crypto::sha2::Sha256;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use zxcvbn::zxcvbn;
 use std::io::Result as IoResult;
 use types::{ApiError, ValidationError};
 use super::models::User;
@@ -8,7 +14,11 @@ use diesel::dsl::exists;
 use diesel::select;
 use diesel::prelude::*;
 
+use chrono::{Duration, Utc};
 use jwt::{Header, Registered, Token};
+use config::CONFIG;
+use db::{lower, username_skeleton};
+use unicode_normalization::UnicodeNormalization;
 
 lazy_static!{
     static ref EMAIL_RE: Regex = {
@@ -17,6 +27,18 @@ lazy_static!{
     };
 }
 
+/// Form emails are stored and looked up in: trimmed, NFKC normalized and
+/// lowercased.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().nfkc().collect::<String>().to_lowercase()
+}
+
+/// Form usernames are stored in. Casing is kept for display, uniqueness and
+/// lookups ignore it.
+pub fn normalize_username(username: &str) -> String {
+    username.trim().nfkc().collect()
+}
+
 pub fn validate_email_re(email: &str) -> Result<(), ValidationError> {
     if !EMAIL_RE.is_match(email) {
         Err(ValidationError::from(
@@ -28,17 +50,6 @@ pub fn validate_email_re(email: &str) -> Result<(), ValidationError> {
     }
 }
 
-pub fn validate_username_re(username: &str) -> Result<(), ValidationError> {
-    if username.len() < 3 {
-        Err(ValidationError::from(
-            "email",
-            format!("username to small: {}", username),
-        ))
-    } else {
-        Ok(())
-    }
-}
-
 pub fn validate_email(email_to_validate: &str, connection: &PgConnection) -> Result<(), ApiError> {
     use db::schema::users::dsl::*;
     let mut errors = ValidationError::default();
@@ -46,8 +57,8 @@ pub fn validate_email(email_to_validate: &str, connection: &PgConnection) -> Res
         errors.add_error("email", format!("Invalid email: {}", email_to_validate));
     }
 
-    let email_exists =
-        select(exists(users.filter(email.eq(email_to_validate)))).get_result::<bool>(connection)?;
+    let email_exists = select(exists(users.filter(lower(email).eq(email_to_validate))))
+        .get_result::<bool>(connection)?;
     if email_exists {
         errors.add_error("email", "Email allready exists");
     }
@@ -58,31 +69,228 @@ pub fn validate_email(email_to_validate: &str, connection: &PgConnection) -> Res
     }
 }
 
-pub fn validate_password(password: &str) -> Result<(), ValidationError> {
-    if password.len() < 5 {
-        let e = ValidationError::from("password", "Password to short");
-        Err(e)
+/// Whether `password` shows up in the local breached password list. Only
+/// the file for the first five hex digits of its SHA-1 is read, and an
+/// unreadable list counts as no match.
+fn is_breached(password: &str) -> bool {
+    let dir = match CONFIG.password.breached_dir {
+        Some(ref dir) => dir,
+        None => return false,
+    };
+
+    let mut hasher = Sha1::new();
+    hasher.input_str(password);
+    let hash = hasher.result_str().to_uppercase();
+    let (prefix, suffix) = hash.split_at(5);
+    let file = match File::open(Path::new(dir).join(prefix)) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+
+    BufReader::new(file)
+        .lines()
+        .filter_map(|line| line.ok())
+        .any(|line| line.split(':').next().map(|s| s.trim()) == Some(suffix))
+}
+
+/// Checks `password` against the password policy. `user_inputs` are the
+/// username and email of the account, which the password must not contain.
+pub fn validate_password(password: &str, user_inputs: &[&str]) -> Result<(), ValidationError> {
+    let policy = &CONFIG.password;
+    // Checked on its own and first, the other checks get slow on huge input.
+    if password.len() > policy.max_length {
+        return Err(ValidationError::from(
+            "password",
+            format!("Password must be at most {} bytes long", policy.max_length),
+        ));
+    }
+
+    let mut errors = ValidationError::default();
+    if password.chars().count() < policy.min_length {
+        errors.add_error(
+            "password",
+            format!("Password must be at least {} characters long", policy.min_length),
+        );
+    }
+
+    let lowercase = password.to_lowercase();
+    let contains_user_input = user_inputs
+        .iter()
+        .map(|input| input.split('@').next().unwrap_or("").to_lowercase())
+        .any(|input| input.chars().count() >= 3 && lowercase.contains(&input));
+    if contains_user_input {
+        errors.add_error(
+            "password",
+            "Password must not contain your username or email address",
+        );
+    }
+
+    match zxcvbn(password, user_inputs) {
+        Ok(ref entropy) if entropy.score < policy.min_score => {
+            errors.add_error("password", "Password is too easy to guess");
+        }
+        _ => {}
+    }
+
+    if is_breached(password) {
+        errors.add_error(
+            "password",
+            "Password has appeared in a data breach, please choose another one",
+        );
+    }
+
+    if errors.empty() {
+        Ok(())
     } else {
+        Err(errors)
+    }
+}
+
+// Letters that look alike across scripts, mapped to the Latin letter they
+// imitate, plus digits and separators that pass for letters or each other.
+// The SQL function `username_skeleton()` carries a copy of this table for
+// its index, change both together.
+const HOMOGLYPHS: &[(char, char)] = &[
+    ('а', 'a'), ('в', 'b'), ('е', 'e'), ('к', 'k'), ('м', 'm'), ('н', 'h'), ('о', 'o'),
+    ('р', 'p'), ('с', 'c'), ('т', 't'), ('у', 'y'), ('х', 'x'), ('і', 'i'), ('ј', 'j'),
+    ('ѕ', 's'), ('ԁ', 'd'), ('һ', 'h'), ('ӏ', 'l'), ('α', 'a'), ('β', 'b'), ('ε', 'e'),
+    ('ι', 'i'), ('κ', 'k'), ('ν', 'v'), ('ο', 'o'), ('ρ', 'p'), ('τ', 't'), ('υ', 'u'),
+    ('χ', 'x'), ('0', 'o'), ('1', 'l'), ('5', 's'), ('-', '_'), ('.', '_'),
+];
+
+/// Usernames only differing in homoglyphs share a skeleton. Mirrors what
+/// `username_skeleton(username)` yields in SQL.
+fn skeleton(username: &str) -> String {
+    username
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            HOMOGLYPHS
+                .iter()
+                .find(|&&(from, _)| from == c)
+                .map_or(c, |&(_, to)| to)
+        })
+        .collect()
+}
+
+#[derive(PartialEq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Other,
+}
+
+fn script(c: char) -> Script {
+    match c as u32 {
+        0x0000...0x024F => Script::Latin,
+        0x0370...0x03FF => Script::Greek,
+        0x0400...0x052F => Script::Cyrillic,
+        _ => Script::Other,
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '_' || c == '-' || c == '.'
+}
+
+/// Checks `username` against the rules that do not need the database:
+/// length, character set, a single script and the reserved names.
+pub fn username_policy(username: &str) -> Result<(), ValidationError> {
+    let policy = &CONFIG.username;
+    let mut errors = ValidationError::default();
+
+    let length = username.chars().count();
+    if length < policy.min_length || length > policy.max_length {
+        errors.add_error(
+            "username",
+            format!(
+                "Username must be between {} and {} characters long",
+                policy.min_length, policy.max_length
+            ),
+        );
+    }
+
+    let allowed = username
+        .chars()
+        .all(|c| c.is_alphabetic() || c.is_ascii_digit() || is_separator(c));
+    if !allowed {
+        errors.add_error(
+            "username",
+            "Username may only contain letters, digits, '_', '-' and '.'",
+        );
+    }
+
+    let starts_or_ends_with_separator = username.chars().next().map_or(false, is_separator)
+        || username.chars().last().map_or(false, is_separator);
+    let repeated_separator = username
+        .chars()
+        .zip(username.chars().skip(1))
+        .any(|(a, b)| is_separator(a) && is_separator(b));
+    if starts_or_ends_with_separator || repeated_separator {
+        errors.add_error(
+            "username",
+            "Username must start and end with a letter or digit and not repeat separators",
+        );
+    }
+
+    let mut letters = username.chars().filter(|c| c.is_alphabetic());
+    if let Some(first) = letters.next() {
+        let first_script = script(first);
+        if letters.any(|c| script(c) != first_script) {
+            errors.add_error("username", "Username must not mix letters of different scripts");
+        }
+    }
+
+    let username_skeleton = skeleton(username);
+    let reserved = policy
+        .reserved
+        .iter()
+        .any(|name| skeleton(name) == username_skeleton)
+        || username_skeleton.starts_with("deleted_");
+    if reserved {
+        errors.add_error("username", format!("Username is reserved: {}", username));
+    }
+
+    if errors.empty() {
         Ok(())
+    } else {
+        Err(errors)
     }
 }
 
-/*pub fn validate_username(
+/// Full username check for registration and updates: the policy, plus no
+/// other account but `except_id` may hold the same name or one confusable
+/// with it, or have given it up within the reuse cooldown.
+pub fn validate_username(
     username_to_validate: &str,
+    except_id: Option<i32>,
     connection: &PgConnection,
 ) -> Result<(), ApiError> {
-    use db::schema::users::dsl::*;
-    let mut errors = ValidationError::default();
-    let username_exists = select(exists(users.filter(username.eq(username_to_validate))))
+    use db::schema::{username_history, users};
+    username_policy(username_to_validate)?;
+
+    let wanted_skeleton = skeleton(username_to_validate);
+    let except_id = except_id.unwrap_or(0);
+
+    let confusable = username_skeleton(users::username)
+        .eq(&wanted_skeleton)
+        .and(users::id.ne(except_id));
+    let username_exists = select(exists(users::table.filter(confusable)))
         .get_result::<bool>(connection)?;
 
-    if username_exists {
-        errors.add_error("username", "username allready exists");
-    }
+    let cooldown_start = Utc::now() - Duration::days(CONFIG.username.reuse_cooldown_days);
+    let recently_released = username_skeleton(username_history::username)
+        .eq(&wanted_skeleton)
+        .and(username_history::user_id.ne(except_id))
+        .and(username_history::changed_at.gt(cooldown_start));
+    let username_cooling_down = select(exists(username_history::table.filter(recently_released)))
+        .get_result::<bool>(connection)?;
 
-    if errors.len() > 0 {
-        Err(errors.into())
+    if username_exists || username_cooling_down {
+        let error = ValidationError::from("username", "Username is already taken");
+        Err(error.into())
     } else {
         Ok(())
     }
-}*/
+}
@@ -0,0 +1,38 @@
+use chrono::Utc;
+use db::schema::users;
+use diesel::prelude::*;
+use diesel::update as diesel_update;
+use mailer::{app_link, Mail, Mailer};
+use types::{ApiError, ValidationError};
+use super::claims;
+use super::models::User;
+
+/// Mails `user` a link that confirms their current email address.
+pub fn send(user: &User, mailer: &Mailer) -> Result<(), ApiError> {
+    let token = user.verification_token()?;
+    let link = app_link(&format!("/verify-email?token={}", token));
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Hi {},\n\nplease confirm your email address by opening this link:\n{}\n",
+            user.username, link
+        ),
+    };
+    mailer.send(&mail)
+}
+
+/// Marks the owner of `token` as verified. A token only works once: after
+/// that the address is verified, and it stops matching if the email changes.
+pub fn confirm(token: &str, connection: &PgConnection) -> Result<User, ApiError> {
+    let mut user = User::load_from_claims(token, Some(claims::VERIFY_EMAIL), connection)?;
+    if user.is_verified() {
+        return Err(ValidationError::from("token", "Email already verified").into());
+    }
+
+    user.email_verified_at = Some(Utc::now());
+    diesel_update(&user)
+        .set(users::email_verified_at.eq(user.email_verified_at))
+        .execute(connection)?;
+    Ok(user)
+}
@@ -2,9 +2,15 @@ use serde_json::{self, Value};
 use rocket::response::content::Json;
 use rocket::response::{Responder, Response};
 use rocket::http::Status;
-use rocket::request::Request;
+use rocket::request::{self, FromRequest, Request};
+use rocket::Outcome;
+use std::net::IpAddr;
 use chrono::{DateTime, SecondsFormat, Utc};
 use serde::Serializer;
+use rand::{OsRng, Rng};
+use std::io::Result as IoResult;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
 
 pub fn try_respond(
     req: &Request,
@@ -21,6 +27,17 @@ pub fn try_respond(
     }
 }
 
+/// Address of the peer that sent the request, if known.
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for ClientIp {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<ClientIp, ()> {
+        Outcome::Success(ClientIp(request.remote().map(|addr| addr.ip())))
+    }
+}
+
 pub fn serialize_date<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -28,3 +45,39 @@ where
     let s = date.to_rfc3339_opts(SecondsFormat::Millis, true);
     serializer.serialize_str(&s)
 }
+
+pub fn serialize_optional_date<S>(
+    date: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match *date {
+        Some(ref date) => serialize_date(date, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Returns `len` random bytes from the OS generator.
+pub fn random_bytes(len: usize) -> IoResult<Vec<u8>> {
+    let mut rng = OsRng::new()?;
+    Ok(rng.gen_iter::<u8>().take(len).collect())
+}
+
+/// Returns `len` random bytes from the OS generator, hex encoded.
+pub fn random_token(len: usize) -> IoResult<String> {
+    let token = random_bytes(len)?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    Ok(token)
+}
+
+/// Hex encoded SHA-256 of `value`. Good enough for storing long random
+/// tokens, never use it for passwords.
+pub fn sha256_hex(value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input_str(value);
+    hasher.result_str()
+}